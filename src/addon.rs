@@ -1,7 +1,8 @@
 use jiff::Timestamp;
-use serde::{Deserialize, Serialize, de::Deserializer};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize)]
+/// Addon as stored in the registry
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Addon {
     pub id: i32,
@@ -14,86 +15,6 @@ pub struct Addon {
     pub date_modified: Timestamp,
 }
 
-impl<'de> Deserialize<'de> for Addon {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        #[derive(Deserialize)]
-        #[serde(untagged)]
-        enum AddonWrapper {
-            Api(ApiResponse),
-            Disk(DiskAddonWrapper),
-        }
-
-        #[derive(Deserialize)]
-        #[serde(rename_all = "camelCase")]
-        struct DiskAddonWrapper {
-            id: i32,
-            status: i32,
-            name: String,
-            slug: String,
-            summary: String,
-            authors: Vec<AddonAuthor>,
-            main_file: AddonFile,
-            date_modified: Timestamp,
-        }
-
-        #[derive(Deserialize)]
-        struct ApiResponse {
-            data: ApiAddonWrapper,
-        }
-
-        #[derive(Deserialize)]
-        #[serde(rename_all = "camelCase")]
-        struct ApiAddonWrapper {
-            id: i32,
-            name: String,
-            status: i32,
-            slug: String,
-            summary: String,
-            authors: Vec<AddonAuthor>,
-            main_file_id: i32,
-            latest_files: Vec<AddonFile>,
-            date_modified: Timestamp,
-        }
-
-        let wrapper = AddonWrapper::deserialize(deserializer)?;
-
-        match wrapper {
-            AddonWrapper::Disk(inner) => Ok(Self {
-                id: inner.id,
-                name: inner.name,
-                status: inner.status,
-                slug: inner.slug,
-                summary: inner.summary,
-                authors: inner.authors,
-                main_file: inner.main_file,
-                date_modified: inner.date_modified,
-            }),
-            AddonWrapper::Api(inner) => {
-                let inner = inner.data;
-                let main_file = inner
-                    .latest_files
-                    .into_iter()
-                    .find(|m| m.id == inner.main_file_id)
-                    .expect("if the main file id isn't here then something has went wrong");
-
-                Ok(Self {
-                    id: inner.id,
-                    name: inner.name,
-                    status: inner.status,
-                    slug: inner.slug,
-                    summary: inner.summary,
-                    authors: inner.authors,
-                    main_file,
-                    date_modified: inner.date_modified,
-                })
-            }
-        }
-    }
-}
-
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct AddonAuthor {
     pub id: i32,
@@ -101,7 +22,7 @@ pub struct AddonAuthor {
     pub url: Option<String>,
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AddonFile {
     pub id: i32,
@@ -116,75 +37,144 @@ pub struct AddonFile {
     pub modules: Vec<String>,
 }
 
-impl<'de> Deserialize<'de> for AddonFile {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        #[derive(Deserialize)]
-        #[serde(untagged)]
-        enum HashWrapper {
-            Disk(Vec<String>),
-            Api(Vec<ApiHashWrapper>),
-        }
-
-        #[derive(Deserialize)]
-        #[serde(untagged)]
-        enum ModuleWrapper {
-            Disk(Vec<String>),
-            Api(Vec<AddonModule>),
-        }
+#[derive(Debug, thiserror::Error)]
+pub enum AddonError {
+    #[error("main file {main_file_id} of addon {id} is missing from its latest files")]
+    MissingMainFile { id: i32, main_file_id: i32 },
+}
 
-        #[derive(Deserialize)]
-        struct ApiHashWrapper {
-            value: String,
-        }
+/// Envelope around every CurseForge API response
+#[derive(Deserialize)]
+pub struct ApiResponse<T> {
+    pub data: T,
+}
 
-        #[derive(Deserialize)]
-        #[serde(rename_all = "camelCase")]
-        struct AddonFileWrapper {
-            id: i32,
-            mod_id: i32,
-            is_available: bool,
-            display_name: Option<String>,
-            file_name: String,
-            hashes: HashWrapper,
-            modules: ModuleWrapper,
-            file_date: Timestamp,
-            download_url: Option<String>,
-            game_versions: Vec<String>,
-        }
+/// Addon as returned by the CurseForge API
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiAddon {
+    pub id: i32,
+    pub name: String,
+    pub status: i32,
+    pub slug: String,
+    pub summary: String,
+    pub authors: Vec<AddonAuthor>,
+    pub main_file_id: i32,
+    pub latest_files: Vec<ApiAddonFile>,
+    #[serde(default)]
+    pub latest_files_indexes: Vec<ApiFileIndex>,
+    pub date_modified: Timestamp,
+}
 
-        let wrapper = AddonFileWrapper::deserialize(deserializer)?;
+/// Latest file of an addon for a single game version
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiFileIndex {
+    pub file_id: i32,
+    pub release_type: i32,
+    pub game_version_type_id: Option<i32>,
+}
 
-        let hashes = match wrapper.hashes {
-            HashWrapper::Api(api_hash) => api_hash.into_iter().map(|hash| hash.value).collect(),
-            HashWrapper::Disk(disk_hash) => disk_hash,
-        };
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiAddonFile {
+    pub id: i32,
+    pub mod_id: i32,
+    pub is_available: bool,
+    pub display_name: Option<String>,
+    pub file_name: String,
+    pub hashes: Vec<ApiFileHash>,
+    pub modules: Vec<AddonModule>,
+    pub file_date: Timestamp,
+    pub download_url: Option<String>,
+    pub game_versions: Vec<String>,
+}
 
-        let modules = match wrapper.modules {
-            ModuleWrapper::Api(api_modules) => api_modules.into_iter().map(|m| m.name).collect(),
-            ModuleWrapper::Disk(disk_modules) => disk_modules,
-        };
+const RELEASE_TYPE_RELEASE: i32 = 1;
+
+impl ApiAddon {
+    /// Pick the file to use for the given CurseForge game version types.
+    ///
+    /// The main file is preferred if it supports one of the types, otherwise the
+    /// newest release (falling back to any release type) is used.
+    pub fn flavor_file_id(&self, type_ids: &[i32]) -> Option<i32> {
+        let candidates: Vec<&ApiFileIndex> = self
+            .latest_files_indexes
+            .iter()
+            .filter(|index| {
+                index
+                    .game_version_type_id
+                    .is_some_and(|id| type_ids.contains(&id))
+            })
+            .collect();
+
+        if candidates
+            .iter()
+            .any(|index| index.file_id == self.main_file_id)
+        {
+            return Some(self.main_file_id);
+        }
 
-        Ok(Self {
-            id: wrapper.id,
-            mod_id: wrapper.mod_id,
-            is_available: wrapper.is_available,
-            display_name: wrapper.display_name,
-            file_name: wrapper.file_name,
-            hashes,
-            modules,
-            file_date: wrapper.file_date,
-            download_url: wrapper.download_url,
-            game_versions: wrapper.game_versions,
-        })
+        candidates
+            .iter()
+            .filter(|index| index.release_type == RELEASE_TYPE_RELEASE)
+            .map(|index| index.file_id)
+            .max()
+            .or_else(|| candidates.iter().map(|index| index.file_id).max())
     }
 }
 
+#[derive(Deserialize)]
+pub struct ApiFileHash {
+    pub value: String,
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct AddonModule {
     pub name: String,
     pub fingerprint: usize,
 }
+
+impl TryFrom<ApiAddon> for Addon {
+    type Error = AddonError;
+
+    fn try_from(api: ApiAddon) -> Result<Self, Self::Error> {
+        let main_file = api
+            .latest_files
+            .into_iter()
+            .find(|file| file.id == api.main_file_id)
+            .ok_or(AddonError::MissingMainFile {
+                id: api.id,
+                main_file_id: api.main_file_id,
+            })?;
+
+        Ok(Self {
+            id: api.id,
+            name: api.name,
+            status: api.status,
+            slug: api.slug,
+            summary: api.summary,
+            authors: api.authors,
+            main_file: main_file.into(),
+            date_modified: api.date_modified,
+        })
+    }
+}
+
+impl From<ApiAddonFile> for AddonFile {
+    fn from(api: ApiAddonFile) -> Self {
+        Self {
+            id: api.id,
+            mod_id: api.mod_id,
+            is_available: api.is_available,
+            display_name: api.display_name,
+            file_name: api.file_name,
+            hashes: api.hashes.into_iter().map(|hash| hash.value).collect(),
+            modules: api.modules.into_iter().map(|m| m.name).collect(),
+            file_date: api.file_date,
+            download_url: api.download_url,
+            game_versions: api.game_versions,
+        }
+    }
+}
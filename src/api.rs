@@ -1,59 +1,390 @@
-use crate::addon::Addon;
+use crate::{
+    addon::{Addon, ApiAddon, ApiAddonFile, ApiResponse},
+    store::{GameVersion, path::verify_hashes},
+};
 use anyhow::{Context, Result};
-use reqwest::Client;
+use indicatif::ProgressBar;
+use reqwest::{Certificate, Client, Proxy, StatusCode, header::RANGE};
+use serde::de::DeserializeOwned;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{Mutex, OwnedSemaphorePermit, Semaphore},
+    time::Instant,
+};
 
-const BASE_URL: &str = "https://api.curseforge.com";
+pub const DEFAULT_BASE_URL: &str = "https://api.curseforge.com";
+pub const DEFAULT_CDN_HOST: &str = "https://edge.forgecdn.net";
+const WOW_GAME_ID: i32 = 1;
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Tuning options for the [`CurseClient`]
+pub struct ClientOptions {
+    /// Maximum number of requests / downloads in flight at once
+    pub max_parallel_downloads: usize,
+
+    /// Sustained request rate allowed against CurseForge
+    pub requests_per_second: u32,
+
+    /// Number of times a failed download is retried before giving up
+    pub download_retries: u32,
+
+    /// Root of the CurseForge API (or a mirror of it)
+    pub base_url: String,
+
+    /// Host used to build download URLs when the API doesn't provide one
+    pub cdn_host: String,
+
+    /// Time allowed to establish a connection
+    pub connect_timeout: Duration,
+
+    /// Time allowed between reads before a request is abandoned
+    pub read_timeout: Duration,
+
+    /// HTTP(S) or SOCKS proxy all traffic is routed through
+    pub proxy: Option<String>,
+
+    /// Extra PEM encoded CA certificates to trust
+    pub ca_certificates: Vec<PathBuf>,
+
+    /// User-Agent header sent with every request
+    pub user_agent: String,
+}
 
 pub struct CurseClient {
     api: Client,
     key: String,
+    slots: Arc<Semaphore>,
+    limiter: RateLimiter,
+    retries: u32,
+    base_url: String,
+    cdn_host: String,
 }
 
 impl CurseClient {
-    pub fn new(api_key: &str) -> Self {
-        Self {
-            api: Client::new(),
+    pub fn new(api_key: &str, options: ClientOptions) -> Result<Self> {
+        let mut builder = Client::builder()
+            .connect_timeout(options.connect_timeout)
+            .read_timeout(options.read_timeout)
+            .user_agent(&options.user_agent);
+
+        if let Some(ref proxy) = options.proxy {
+            let proxy = Proxy::all(proxy).with_context(|| format!("invalid proxy '{proxy}'"))?;
+            builder = builder.proxy(proxy);
+        }
+
+        let mut certificates = Vec::new();
+        for path in options.ca_certificates.iter() {
+            let pem = std::fs::read(path)
+                .with_context(|| format!("reading CA certificate {}", path.display()))?;
+            let bundle = Certificate::from_pem_bundle(&pem)
+                .with_context(|| format!("parsing CA certificate {}", path.display()))?;
+            certificates.extend(bundle);
+        }
+
+        let api = builder
+            .tls_certs_merge(certificates)
+            .build()
+            .context("building http client")?;
+
+        Ok(Self {
+            api,
             key: api_key.to_string(),
+            slots: Arc::new(Semaphore::new(options.max_parallel_downloads)),
+            limiter: RateLimiter::new(options.requests_per_second),
+            retries: options.download_retries,
+            base_url: options.base_url.trim_end_matches('/').to_string(),
+            cdn_host: options.cdn_host.trim_end_matches('/').to_string(),
+        })
+    }
+
+    /// Wait for a free slot before spawning work against the API.
+    ///
+    /// The permit should be held for the lifetime of the task.
+    pub async fn acquire_slot(&self) -> Result<OwnedSemaphorePermit> {
+        Arc::clone(&self.slots)
+            .acquire_owned()
+            .await
+            .context("acquiring client slot")
+    }
+
+    /// Fetch an addon using the file for the given game version, or the main file
+    /// if there is no build specifically for it
+    pub async fn get_addon(&self, addon_id: i32, version: GameVersion) -> Result<Addon> {
+        let api = self.fetch_addon(addon_id).await?;
+        let file_id = api
+            .flavor_file_id(version.game_version_type_ids())
+            .unwrap_or(api.main_file_id);
+
+        self.resolve(api, file_id).await
+    }
+
+    /// Fetch an addon only if it has a build for the given game version
+    pub async fn get_flavor_addon(
+        &self,
+        addon_id: i32,
+        version: GameVersion,
+    ) -> Result<Option<Addon>> {
+        let api = self.fetch_addon(addon_id).await?;
+        let Some(file_id) = api.flavor_file_id(version.game_version_type_ids()) else {
+            return Ok(None);
+        };
+
+        self.resolve(api, file_id).await.map(Some)
+    }
+
+    /// Make a cheap authenticated request to check the API key works
+    pub async fn check_api_key(&self) -> Result<()> {
+        let url = format!("{}/v1/games/{WOW_GAME_ID}", self.base_url);
+        self.get_json::<serde_json::Value>(&url)
+            .await
+            .context("checking api key")?;
+
+        Ok(())
+    }
+
+    async fn fetch_addon(&self, addon_id: i32) -> Result<ApiAddon> {
+        let url = format!("{}/v1/mods/{addon_id}", self.base_url);
+        let response: ApiResponse<ApiAddon> = self
+            .get_json(&url)
+            .await
+            .with_context(|| format!("fetching addon {addon_id}"))?;
+
+        Ok(response.data)
+    }
+
+    /// Build the addon with `file_id` as its main file, fetching the file if it
+    /// isn't one of the latest files
+    async fn resolve(&self, mut api: ApiAddon, file_id: i32) -> Result<Addon> {
+        if !api.latest_files.iter().any(|file| file.id == file_id) {
+            let url = format!("{}/v1/mods/{}/files/{file_id}", self.base_url, api.id);
+            let response: ApiResponse<ApiAddonFile> = self
+                .get_json(&url)
+                .await
+                .with_context(|| format!("fetching file {file_id} of addon {}", api.id))?;
+            api.latest_files.push(response.data);
         }
+
+        api.main_file_id = file_id;
+        Addon::try_from(api).context("converting api addon")
     }
 
-    pub async fn get_addon(&self, addon_id: i32) -> Result<Addon> {
-        let url = format!("{BASE_URL}/v1/mods/{addon_id}");
+    async fn get_json<T: DeserializeOwned>(&self, url: &str) -> Result<T> {
+        self.limiter.acquire().await;
         self.api
-            .get(&url)
+            .get(url)
             .header("Accept", "application/json")
             .header("x-api-key", &self.key)
             .send()
             .await
             .context("calling api")?
-            .json::<Addon>()
+            .error_for_status()
+            .context("api response status")?
+            .json::<T>()
             .await
-            .context("converting to text")
+            .context("deserialising api response")
     }
 
-    pub async fn download_addon(&self, addon: &Addon) -> Result<Vec<u8>> {
+    /// Stream the main file of the addon to `dst`.
+    ///
+    /// The body is written to a `.part` file alongside `dst` which is kept between
+    /// attempts so interrupted downloads can be resumed. The file is only renamed
+    /// into place once it matches the hashes reported by CurseForge.
+    pub async fn download_addon(
+        &self,
+        addon: &Addon,
+        dst: &Path,
+        progress: &ProgressBar,
+    ) -> Result<()> {
         let url = if let Some(ref url) = addon.main_file.download_url {
             url
         } else {
             &format!(
-                "https://edge.forgecdn.net/files/{}/{}/{}",
+                "{}/files/{}/{}/{}",
+                self.cdn_host,
                 addon.main_file.id / 1000,
                 addon.main_file.id % 1000,
                 addon.main_file.file_name
             )
         };
 
-        let content = self
-            .api
-            .get(url)
+        if let Some(dir) = dst.parent() {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .with_context(|| format!("creating directory {}", dir.display()))?;
+        }
+
+        let part = partial_path(dst);
+        let mut attempt = 0;
+        loop {
+            match self.download_part(url, &part, progress).await {
+                Ok(()) => {
+                    let part_path = part.clone();
+                    let hashes = addon.main_file.hashes.clone();
+                    let valid =
+                        tokio::task::spawn_blocking(move || verify_hashes(&part_path, &hashes))
+                            .await
+                            .context("joining hash task")??;
+
+                    if valid {
+                        break;
+                    }
+
+                    tokio::fs::remove_file(&part)
+                        .await
+                        .with_context(|| format!("removing corrupt download {}", part.display()))?;
+
+                    if attempt >= self.retries {
+                        anyhow::bail!("hash mismatch for {}", addon.main_file.file_name);
+                    }
+
+                    eprintln!(
+                        "Hash mismatch for {}, retrying ({}/{})...",
+                        addon.name,
+                        attempt + 1,
+                        self.retries
+                    );
+                }
+                Err(e) if attempt < self.retries => {
+                    eprintln!(
+                        "Download of {} failed: {e:#}, retrying ({}/{})...",
+                        addon.name,
+                        attempt + 1,
+                        self.retries
+                    );
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!("downloading {} after {} attempts", addon.name, attempt + 1)
+                    });
+                }
+            }
+
+            attempt += 1;
+            tokio::time::sleep(RETRY_DELAY * attempt).await;
+        }
+
+        tokio::fs::rename(&part, dst)
+            .await
+            .with_context(|| format!("moving {} into place", part.display()))?;
+
+        Ok(())
+    }
+
+    /// Download `url` into `part`, resuming from the end of any existing content
+    async fn download_part(&self, url: &str, part: &Path, progress: &ProgressBar) -> Result<()> {
+        let offset = match tokio::fs::metadata(part).await {
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        };
+
+        self.limiter.acquire().await;
+        let mut request = self.api.get(url);
+        if offset > 0 {
+            request = request.header(RANGE, format!("bytes={offset}-"));
+        }
+
+        let response = request
             .send()
             .await
-            .with_context(|| format!("calling download url for {}: {}", addon.name, url))?
-            .bytes()
+            .with_context(|| format!("calling download url {url}"))?;
+
+        // The partial file already holds the whole body
+        if offset > 0 && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            return Ok(());
+        }
+
+        let mut response = response
+            .error_for_status()
+            .with_context(|| format!("downloading {url}"))?;
+
+        let resumed = offset > 0 && response.status() == StatusCode::PARTIAL_CONTENT;
+        let mut file = if resumed {
+            tokio::fs::OpenOptions::new()
+                .append(true)
+                .open(part)
+                .await
+                .with_context(|| format!("opening partial download {}", part.display()))?
+        } else {
+            tokio::fs::File::create(part)
+                .await
+                .with_context(|| format!("creating partial download {}", part.display()))?
+        };
+
+        let start = if resumed { offset } else { 0 };
+        if let Some(length) = response.content_length() {
+            progress.set_length(start + length);
+        }
+        progress.set_position(start);
+
+        while let Some(chunk) = response.chunk().await.context("reading content chunk")? {
+            file.write_all(&chunk)
+                .await
+                .with_context(|| format!("writing to {}", part.display()))?;
+            progress.inc(chunk.len() as u64);
+        }
+
+        file.flush()
             .await
-            .context("getting content bytes")?
-            .to_vec();
+            .with_context(|| format!("flushing {}", part.display()))?;
+
+        Ok(())
+    }
+}
+
+fn partial_path(dst: &Path) -> PathBuf {
+    let mut name = dst.as_os_str().to_owned();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+/// Token bucket shared by every handle to a [`CurseClient`]
+struct RateLimiter {
+    rate: f64,
+    capacity: f64,
+    bucket: Mutex<Bucket>,
+}
 
-        Ok(content)
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    fn new(requests_per_second: u32) -> Self {
+        let rate = f64::from(requests_per_second.max(1));
+        Self {
+            rate,
+            capacity: rate,
+            bucket: Mutex::new(Bucket {
+                tokens: rate,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().await;
+                let now = Instant::now();
+                let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+                bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.capacity);
+                bucket.last_refill = now;
+
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+
+                Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate)
+            };
+
+            tokio::time::sleep(wait).await;
+        }
     }
 }
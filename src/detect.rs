@@ -0,0 +1,153 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use crate::store::{GameVersion, VERSIONS};
+
+const WOW_DIR: &str = "World of Warcraft";
+
+/// Extra directories to search, separated like `PATH`
+const SEARCH_PATHS_VAR: &str = "MOXEN_WOW_SEARCH_PATHS";
+
+/// A World of Warcraft install found on disk
+pub struct Installation {
+    pub root: PathBuf,
+    pub flavors: Vec<GameVersion>,
+}
+
+impl std::fmt::Display for Installation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let flavors: Vec<String> = self.flavors.iter().map(|v| v.to_string()).collect();
+        write!(f, "{} ({})", self.root.display(), flavors.join(", "))
+    }
+}
+
+/// Search the usual install locations (and any user provided roots) for WoW
+pub fn installations() -> Vec<Installation> {
+    let mut candidates = user_roots();
+    candidates.extend(platform_candidates());
+
+    let mut seen = HashSet::new();
+    let mut found = Vec::new();
+    for candidate in candidates {
+        let Some(root) = wow_root(&candidate) else {
+            continue;
+        };
+
+        let key = root.canonicalize().unwrap_or_else(|_| root.clone());
+        if !seen.insert(key) {
+            continue;
+        }
+
+        let flavors = flavors(&root);
+        found.push(Installation { root, flavors });
+    }
+
+    found
+}
+
+/// Game versions installed under a WoW root
+pub fn flavors(root: &Path) -> Vec<GameVersion> {
+    VERSIONS
+        .into_iter()
+        .filter(|version| root.join(version.suffix()).is_dir())
+        .collect()
+}
+
+/// Game version to start on for an install, retail unless only other flavors exist
+pub fn preferred_flavor(root: &Path) -> GameVersion {
+    let flavors = flavors(root);
+    if flavors.contains(&GameVersion::Retail) {
+        GameVersion::Retail
+    } else {
+        flavors.first().copied().unwrap_or_default()
+    }
+}
+
+/// Resolve a candidate to a WoW root: either the root itself or a
+/// `World of Warcraft` directory inside it
+fn wow_root(candidate: &Path) -> Option<PathBuf> {
+    [candidate.to_path_buf(), candidate.join(WOW_DIR)]
+        .into_iter()
+        .find(|path| path.is_dir() && !flavors(path).is_empty())
+}
+
+fn user_roots() -> Vec<PathBuf> {
+    std::env::var_os(SEARCH_PATHS_VAR)
+        .map(|paths| std::env::split_paths(&paths).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(target_os = "linux")]
+fn platform_candidates() -> Vec<PathBuf> {
+    let Some(home) = std::env::var_os("HOME").map(PathBuf::from) else {
+        return Vec::new();
+    };
+
+    // Prefixes containing a `drive_c`
+    let mut prefixes = vec![home.join(".wine")];
+    if let Some(prefix) = std::env::var_os("WINEPREFIX") {
+        prefixes.push(PathBuf::from(prefix));
+    }
+
+    // Lutris installs into ~/Games/<game> by default
+    prefixes.extend(children(&home.join("Games")));
+
+    // Bottles, native and flatpak
+    for bottles in [
+        home.join(".local/share/bottles/bottles"),
+        home.join(".var/app/com.usebottles.bottles/data/bottles/bottles"),
+    ] {
+        prefixes.extend(children(&bottles));
+    }
+
+    // Steam Proton, one prefix per non-Steam game
+    for steam in [
+        home.join(".steam/steam"),
+        home.join(".local/share/Steam"),
+        home.join(".var/app/com.valvesoftware.Steam/data/Steam"),
+    ] {
+        prefixes.extend(
+            children(&steam.join("steamapps/compatdata"))
+                .into_iter()
+                .map(|prefix| prefix.join("pfx")),
+        );
+    }
+
+    let mut candidates = Vec::new();
+    for prefix in prefixes {
+        let drive = prefix.join("drive_c");
+        candidates.push(drive.join("Program Files (x86)"));
+        candidates.push(drive.join("Program Files"));
+        // Some installers put the game directly in the prefix dir
+        candidates.push(prefix.join(WOW_DIR));
+    }
+
+    candidates
+}
+
+#[cfg(not(target_os = "linux"))]
+fn platform_candidates() -> Vec<PathBuf> {
+    if cfg!(windows) {
+        vec![
+            PathBuf::from(r"C:\Program Files (x86)"),
+            PathBuf::from(r"C:\Program Files"),
+        ]
+    } else {
+        vec![PathBuf::from("/Applications")]
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn children(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect()
+}
@@ -0,0 +1,411 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use crate::{
+    addon::Addon,
+    api::CurseClient,
+    store::{
+        GameVersion, MoxenConfig, VERSIONS, cache,
+        path::{MoxenPath, verify_hashes},
+        registry::{self, MoxenRegistry},
+    },
+};
+
+/// Registries that could be read, by profile and game version
+type Registries = Vec<(String, GameVersion, MoxenRegistry)>;
+
+/// Findings of a health check
+#[derive(Default)]
+struct Report {
+    problems: usize,
+}
+
+impl Report {
+    fn ok(&self, msg: impl AsRef<str>) {
+        println!("[ok] {}", msg.as_ref());
+    }
+
+    fn skipped(&self, msg: impl AsRef<str>) {
+        println!("[--] {}", msg.as_ref());
+    }
+
+    fn problem(&mut self, msg: impl AsRef<str>, fix: impl AsRef<str>) {
+        self.problems += 1;
+        println!("[!!] {}", msg.as_ref());
+        println!("     fix: {}", fix.as_ref());
+    }
+}
+
+/// Check the config, registries, API keys, install dirs and cache, printing a
+/// fix for every problem found
+pub async fn run(offline: bool) -> Result<()> {
+    let mut report = Report::default();
+
+    if let Some(config) = check_config(&mut report)? {
+        let registries = check_registries(&mut report, &config);
+        if offline {
+            report.skipped("API key check skipped in offline mode");
+        } else {
+            check_api_keys(&mut report, &config).await;
+        }
+        check_install_dirs(&mut report, &config, &registries);
+        check_modules(&mut report, &config, &registries);
+        check_cache(&mut report, &config, &registries);
+    }
+
+    println!();
+    if report.problems > 0 {
+        anyhow::bail!("found {} problem(s)", report.problems);
+    }
+
+    println!("No problems found");
+    Ok(())
+}
+
+fn check_config(report: &mut Report) -> Result<Option<MoxenConfig>> {
+    let path = MoxenPath::lookup_config()
+        .context("loading root moxen path")?
+        .file("config.toml")
+        .build();
+
+    if !path.exists() {
+        report.problem(
+            format!("{} does not exist", path.display()),
+            "run `moxen init`",
+        );
+        return Ok(None);
+    }
+
+    let (config, needs_migration) = match MoxenConfig::load_read_only() {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            report.problem(
+                format!("{} is invalid: {e:#}", path.display()),
+                "correct the file by hand, or move it aside and run `moxen init`",
+            );
+            return Ok(None);
+        }
+    };
+
+    if needs_migration {
+        report.problem(
+            format!("{} needs migration", path.display()),
+            "run any other moxen command, e.g. `moxen list`, to migrate it",
+        );
+    } else {
+        report.ok(format!("config {}", path.display()));
+    }
+
+    if config.profile(&config.active_profile).is_err() {
+        report.problem(
+            format!("active profile '{}' does not exist", config.active_profile),
+            "pick another one with `moxen profile use <NAME>`",
+        );
+    }
+
+    for cert in config.ca_certificates.iter().filter(|cert| !cert.is_file()) {
+        report.problem(
+            format!("CA certificate {} does not exist", cert.display()),
+            "update the list with `moxen config set ca_certificates <PATHS>`",
+        );
+    }
+
+    Ok(Some(config))
+}
+
+fn check_registries(report: &mut Report, config: &MoxenConfig) -> Registries {
+    let mut registries = Vec::new();
+    for profile in config.profiles.keys() {
+        let mut valid = true;
+        for version in VERSIONS {
+            match registry::validate(profile, &version) {
+                Ok(registry) => registries.push((profile.clone(), version, registry)),
+                Err(e) => {
+                    valid = false;
+                    let path = version
+                        .registry_path(profile)
+                        .map(|path| path.display().to_string())
+                        .unwrap_or_else(|_| format!("{profile}/{version} registry"));
+
+                    let fix = if e.is::<registry::NewerSchema>() {
+                        "upgrade moxen to a version that supports it".to_string()
+                    } else if registry::has_valid_backup(profile, &version) {
+                        format!("restore the backup with `mv \"{path}.bak\" \"{path}\"`")
+                    } else {
+                        format!(
+                            "replace it with an empty registry: `echo '{{\"schemaVersion\":{},\"addons\":{{}}}}' > \"{path}\"`",
+                            registry::SCHEMA_VERSION
+                        )
+                    };
+                    report.problem(format!("{path} is invalid: {e:#}"), fix);
+                }
+            }
+        }
+
+        if valid {
+            report.ok(format!("registries of profile '{profile}'"));
+        }
+    }
+
+    registries
+}
+
+async fn check_api_keys(report: &mut Report, config: &MoxenConfig) {
+    let mut checked = HashSet::new();
+    for profile in config.profiles.keys() {
+        let (source, account) = config.key_source(profile);
+        if !checked.insert((source.to_string(), account)) {
+            continue;
+        }
+
+        let label = if config
+            .profile(profile)
+            .is_ok_and(|p| p.api_key_source.is_some())
+        {
+            format!("API key of profile '{profile}'")
+        } else {
+            "API key".to_string()
+        };
+        let set_key = format!(
+            "run `moxen{} config set api_key <KEY>`",
+            profile_flag(config, profile)
+        );
+
+        let key = match config.api_key_for(profile) {
+            Ok(key) => key,
+            Err(e) => {
+                let fix = if source.is_writable() {
+                    set_key
+                } else {
+                    format!(
+                        "make sure {source} provides the key, or {set_key} after `moxen config set api_key_source keyring`"
+                    )
+                };
+                report.problem(format!("{label} can't be read: {e:#}"), fix);
+                continue;
+            }
+        };
+
+        let client = match CurseClient::new(&key, config.client_options()) {
+            Ok(client) => client,
+            Err(e) => {
+                report.problem(
+                    format!("can't create a CurseForge client: {e:#}"),
+                    "check the proxy and ca_certificates settings in `moxen config show`",
+                );
+                return;
+            }
+        };
+
+        match client.check_api_key().await {
+            Ok(()) => report.ok(format!("{label} accepted by CurseForge")),
+            Err(e) => report.problem(
+                format!("{label} was not accepted: {e:#}"),
+                format!("check your network and proxy settings, or {set_key}"),
+            ),
+        }
+    }
+}
+
+fn check_install_dirs(report: &mut Report, config: &MoxenConfig, registries: &Registries) {
+    for (name, profile) in config.profiles.iter() {
+        if !profile.install_dir.0.is_dir() {
+            report.problem(
+                format!(
+                    "install directory {} of profile '{name}' does not exist",
+                    profile.install_dir
+                ),
+                format!(
+                    "run `moxen{} config set install_dir <DIR>`",
+                    profile_flag(config, name)
+                ),
+            );
+            continue;
+        }
+
+        // The profile's current version and any others with tracked addons
+        let in_use = VERSIONS.into_iter().filter(|version| {
+            *version == profile.version
+                || registries
+                    .iter()
+                    .any(|(p, v, registry)| p == name && v == version && !registry.is_empty())
+        });
+
+        for version in in_use {
+            let dir = profile.install_dir.addon_dir(&version);
+            if !dir.is_dir() {
+                report.problem(
+                    format!("{} does not exist", dir.display()),
+                    format!(
+                        "start the {version} client once, or create it with `mkdir -p \"{}\"`",
+                        dir.display()
+                    ),
+                );
+            } else if !is_writable(&dir) {
+                report.problem(
+                    format!("{} is not writable", dir.display()),
+                    format!("check the permissions of {}", dir.display()),
+                );
+            } else {
+                report.ok(format!("{}", dir.display()));
+            }
+        }
+    }
+}
+
+fn check_modules(report: &mut Report, config: &MoxenConfig, registries: &Registries) {
+    let mut checked = 0;
+    for (profile, version, registry) in registries {
+        let Ok(profile_cfg) = config.profile(profile) else {
+            continue;
+        };
+
+        // A missing AddOns dir is already reported
+        let dir = profile_cfg.install_dir.addon_dir(version);
+        if !dir.is_dir() {
+            continue;
+        }
+
+        let mut addons: Vec<_> = registry.values().collect();
+        addons.sort_by(|a, b| a.name.cmp(&b.name));
+        for addon in addons {
+            checked += 1;
+            let missing: Vec<&str> = addon
+                .main_file
+                .modules
+                .iter()
+                .filter(|module| !dir.join(module).is_dir())
+                .map(String::as_str)
+                .collect();
+
+            if !missing.is_empty() {
+                report.problem(
+                    format!(
+                        "{} ({}) in {profile}/{version} is missing {}",
+                        addon.name,
+                        addon.id,
+                        missing.join(", ")
+                    ),
+                    // Online installs skip addons that are up to date
+                    format!(
+                        "run `moxen{}{} install`",
+                        offline_flag(addon),
+                        flags(config, profile, version)
+                    ),
+                );
+            }
+        }
+    }
+
+    report.ok(format!(
+        "checked installed modules of {checked} tracked addons"
+    ));
+}
+
+fn check_cache(report: &mut Report, config: &MoxenConfig, registries: &Registries) {
+    let entries = match cache::entries() {
+        Ok(entries) => entries,
+        Err(e) => {
+            report.problem(
+                format!("the cache can't be read: {e:#}"),
+                "run `moxen clear-cache` and then `moxen update`",
+            );
+            return;
+        }
+    };
+
+    // Hashes of tracked files, and where to update them from
+    let mut expected = HashMap::new();
+    for (profile, version, registry) in registries {
+        for addon in registry.values() {
+            expected.entry((addon.id, addon.main_file.id)).or_insert((
+                &addon.main_file.hashes,
+                profile,
+                version,
+            ));
+        }
+    }
+
+    let mut verified = 0;
+    for entry in entries {
+        let Some(project_id) = entry.project_id else {
+            continue;
+        };
+        for archive in entry.archives {
+            let Some((hashes, profile, version)) = archive
+                .file_id
+                .and_then(|file_id| expected.get(&(project_id, file_id)))
+            else {
+                continue;
+            };
+
+            let problem = match verify_hashes(&archive.path, hashes) {
+                Ok(true) => {
+                    verified += 1;
+                    continue;
+                }
+                Ok(false) => format!(
+                    "cached archive {} of {} doesn't match its hashes",
+                    archive.path.display(),
+                    entry.name
+                ),
+                Err(e) => format!("cached archive of {} can't be read: {e:#}", entry.name),
+            };
+
+            report.problem(
+                problem,
+                format!(
+                    "run `moxen cache clear {project_id}` and then `moxen{} update`",
+                    flags(config, profile, version)
+                ),
+            );
+        }
+    }
+
+    report.ok(format!("verified {verified} cached archives"));
+}
+
+/// `--offline` installs every cached archive, otherwise only updates are installed
+fn offline_flag(addon: &Addon) -> &'static str {
+    if cache::archive_path(addon).is_ok_and(|path| path.exists()) {
+        " --offline"
+    } else {
+        ""
+    }
+}
+
+fn is_writable(dir: &Path) -> bool {
+    let probe = dir.join(".moxen-doctor");
+    let writable = std::fs::File::create(&probe).is_ok();
+    if writable {
+        let _ = std::fs::remove_file(&probe);
+    }
+
+    writable
+}
+
+/// `--profile` argument needed to target a profile, if it isn't the active one
+fn profile_flag(config: &MoxenConfig, profile: &str) -> String {
+    if profile == config.active_profile {
+        String::new()
+    } else {
+        format!(" --profile {profile}")
+    }
+}
+
+/// Arguments needed to target a profile's game version
+fn flags(config: &MoxenConfig, profile: &str, version: &GameVersion) -> String {
+    let mut flags = profile_flag(config, profile);
+    let current = config.profile(profile).map(|p| p.version).ok();
+    if current != Some(*version)
+        && let Some(value) = version.to_possible_value()
+    {
+        flags.push_str(&format!(" --flavor {}", value.get_name()));
+    }
+
+    flags
+}
@@ -1,26 +1,67 @@
 pub mod addon;
 pub mod api;
+pub mod detect;
+pub mod doctor;
+pub mod progress;
 pub mod state;
 pub mod store;
 
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 
-use crate::store::GameVersion;
+use crate::store::{ConfigKey, GameVersion, secret::ApiKeySource};
 
 #[derive(Debug, Parser)]
 #[command(author, version)]
 #[command(propagate_version = true)]
 #[command(about = "CLI for installing World of Warcraft addons via CurseForge", long_about = None)]
 pub struct Cli {
+    /// Only use the registry and cached archives, never contact CurseForge
+    #[arg(long, global = true)]
+    pub offline: bool,
+
+    /// Profile to use instead of the active one
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+
+    /// Game version to use instead of the profile's current one
+    #[arg(long, global = true)]
+    pub flavor: Option<GameVersion>,
+
+    /// Keep the config, registries and cache in this directory
+    #[arg(long, global = true, env = "MOXEN_HOME")]
+    pub home: Option<PathBuf>,
+
     #[clap(subcommand)]
     pub command: MoxenCommand,
 }
 
 #[derive(Debug, PartialEq, Subcommand)]
 pub enum MoxenCommand {
-    /// Initialise Moxen
-    Init,
+    /// Initialise Moxen, or change settings if already initialised
+    Init {
+        /// CurseForge API key
+        #[arg(long, env = "MOXEN_API_KEY", hide_env_values = true)]
+        api_key: Option<String>,
+
+        /// Name of an environment variable holding the CurseForge API key (takes
+        /// precedence over --api-key)
+        #[arg(long)]
+        api_key_env: Option<String>,
+
+        /// Where the API key is kept: keyring, file, env:<VAR> or command:<COMMAND>
+        #[arg(long, env = "MOXEN_API_KEY_SOURCE")]
+        api_key_source: Option<ApiKeySource>,
+
+        /// World of Warcraft install directory
+        #[arg(long, env = "MOXEN_INSTALL_DIR")]
+        install_dir: Option<PathBuf>,
+
+        /// Game version the profile starts on
+        #[arg(long, env = "MOXEN_FLAVOR")]
+        flavor: Option<GameVersion>,
+    },
 
     /// Track new addons in the registry
     Track {
@@ -35,24 +76,227 @@ pub enum MoxenCommand {
     },
 
     /// List tracked addons in the registry
-    List,
+    List {
+        /// Run against every game version
+        #[arg(long, conflicts_with = "flavor")]
+        all_flavors: bool,
+    },
+
+    /// Show a tracked addon and its installed modules
+    Info {
+        /// Addon ID to show
+        addon_id: i32,
+    },
+
+    /// Show which tracked addons are out of date or missing required addons
+    Status,
 
     /// Clear the Moxen file cache
     ClearCache,
 
+    /// Inspect and manage the download cache
+    Cache {
+        #[clap(subcommand)]
+        command: CacheCommand,
+    },
+
     /// Download the latest version of the addon(s)
-    Update,
+    Update {
+        /// Run against every game version
+        #[arg(long, conflicts_with = "flavor")]
+        all_flavors: bool,
+    },
 
     /// Install the addons in the WoW directory
-    Install,
+    Install {
+        /// Run against every game version
+        #[arg(long, conflicts_with = "flavor")]
+        all_flavors: bool,
+    },
+
+    /// Read and change settings
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+
+    /// Back up and restore the WTF folder (addon settings)
+    Backup {
+        #[clap(subcommand)]
+        command: BackupCommand,
+    },
+
+    /// Bring back an addon's folders from before its last install or uninstall
+    Restore {
+        /// Addon ID to restore
+        addon_id: i32,
+    },
+
+    /// Check installed addons against the files recorded when they were installed
+    Verify {
+        /// Addon IDs to check (all tracked addons if empty)
+        addon_ids: Vec<i32>,
+    },
+
+    /// Reinstall addons whose files were modified or deleted
+    Repair {
+        /// Addon IDs to repair (all broken addons if empty)
+        addon_ids: Vec<i32>,
+    },
+
+    /// Check the config, registries, API key, install dirs and cache for problems
+    Doctor,
+
+    /// Manage WoW installation profiles
+    Profile {
+        #[clap(subcommand)]
+        command: ProfileCommand,
+    },
+
+    /// Copy tracked addons from one game version to another
+    Copy {
+        /// Game version to copy from
+        #[arg(long)]
+        from: GameVersion,
+
+        /// Game version to copy to
+        #[arg(long)]
+        to: GameVersion,
+
+        /// Addon IDs to copy (all tracked addons if empty)
+        addon_ids: Vec<i32>,
+
+        /// Install the addons once copied
+        #[arg(long)]
+        install: bool,
+    },
 
     /// Uninstall the selected Addons
     Uninstall {
         /// Addon IDs to remove
         addon_ids: Vec<i32>,
+
+        /// Only stop tracking the addons, leave their folders in the AddOns directory
+        #[arg(long)]
+        keep_files: bool,
+
+        /// Leave the addons' archives in the download cache
+        #[arg(long)]
+        keep_cache: bool,
     },
 }
 
+#[derive(Debug, PartialEq, Subcommand)]
+pub enum CacheCommand {
+    /// Show the size of the cache per addon
+    Info,
+
+    /// Remove old archives and addons that are no longer tracked
+    Prune {
+        /// Number of archives to keep per addon
+        #[arg(long, default_value_t = 1)]
+        keep: usize,
+    },
+
+    /// Clear the cache for a single addon
+    Clear {
+        /// Addon ID to clear
+        addon_id: i32,
+    },
+}
+
+#[derive(Debug, PartialEq, Subcommand)]
+pub enum ConfigCommand {
+    /// Print the value of a setting
+    Get {
+        /// Setting to read
+        key: ConfigKey,
+
+        /// Print the API key instead of hiding it
+        #[arg(long)]
+        show_secret: bool,
+    },
+
+    /// Change a setting (an empty value clears optional settings)
+    Set {
+        /// Setting to change
+        key: ConfigKey,
+
+        /// New value
+        value: String,
+    },
+
+    /// Show all settings with API keys redacted
+    Show,
+}
+
+#[derive(Debug, PartialEq, Subcommand)]
+pub enum ProfileCommand {
+    /// Add a new profile
+    Add {
+        /// Name of the profile
+        name: String,
+
+        /// World of Warcraft install directory for the profile
+        #[arg(long)]
+        install_dir: PathBuf,
+
+        /// Game version the profile starts on
+        #[arg(long, default_value_t = GameVersion::Retail)]
+        flavor: GameVersion,
+
+        /// API key for the profile (uses the global key if not set)
+        #[arg(long)]
+        api_key: Option<String>,
+    },
+
+    /// List profiles
+    List,
+
+    /// Make a profile the active one
+    Use {
+        /// Name of the profile
+        name: String,
+    },
+
+    /// Remove a profile and its registries
+    Remove {
+        /// Name of the profile
+        name: String,
+    },
+}
+
+#[derive(Debug, PartialEq, Subcommand)]
+pub enum BackupCommand {
+    /// Back up the WTF folder of the current game version
+    Create {
+        /// Only back up the SavedVariables of tracked addons
+        #[arg(long)]
+        saved_variables_only: bool,
+    },
+
+    /// List backups of the current game version
+    List,
+
+    /// Restore a backup over the current WTF folder
+    Restore {
+        /// Name of the backup, as shown by `moxen backup list`
+        name: String,
+    },
+}
+
+impl MoxenCommand {
+    /// Whether the command was asked to run against every game version
+    pub fn all_flavors(&self) -> bool {
+        matches!(
+            self,
+            Self::List { all_flavors: true }
+                | Self::Update { all_flavors: true }
+                | Self::Install { all_flavors: true }
+        )
+    }
+}
+
 pub fn is_initialised() -> Result<bool> {
     if !store::MoxenConfig::is_initialised().context("checking moxen initialisation from config")? {
         eprintln!("you must initialise the Moxen app with `moxen init` first");
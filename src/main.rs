@@ -1,44 +1,212 @@
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::{CommandFactory, Parser, error::ErrorKind};
 
-use moxen::{Cli, MoxenCommand, is_initialised, state::MoxenApp};
+use moxen::{
+    BackupCommand, CacheCommand, Cli, ConfigCommand, MoxenCommand, ProfileCommand, is_initialised,
+    state::{AppOptions, MoxenApp},
+    store::{self, AddonInstallPath, GameVersion, InitOptions, Profile},
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
+    if cli.flavor.is_some() && cli.command.all_flavors() {
+        // clap only sees the conflict when --flavor follows the subcommand
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "the argument '--all-flavors' cannot be used with '--flavor <FLAVOR>'",
+            )
+            .exit();
+    }
+    if let Some(home) = cli.home.clone() {
+        store::path::set_home(home);
+    }
+
+    if let MoxenCommand::Init {
+        api_key,
+        api_key_env,
+        api_key_source,
+        install_dir,
+        flavor,
+    } = cli.command
+    {
+        let api_key = match api_key_env {
+            Some(var) => {
+                Some(std::env::var(&var).with_context(|| format!("reading API key from ${var}"))?)
+            }
+            None => api_key,
+        };
 
-    if cli.command == MoxenCommand::Init {
-        MoxenApp::initialise().context("moxen initialisation")?;
+        let options = InitOptions {
+            api_key,
+            api_key_source,
+            install_dir,
+            flavor: flavor.or(cli.flavor),
+        };
+        MoxenApp::initialise(options).context("moxen initialisation")?;
         return Ok(());
     }
 
+    // Runs before the usual start up so it can report a broken config
+    if cli.command == MoxenCommand::Doctor {
+        return moxen::doctor::run(cli.offline).await;
+    }
+
     if !is_initialised().context("initialisation")? {
         return Ok(());
     }
 
-    let mut state = MoxenApp::new().context("loading application")?;
+    let options = AppOptions {
+        offline: cli.offline,
+        profile: cli.profile,
+        flavor: cli.flavor,
+    };
+    let mut state = MoxenApp::new(options).context("loading application")?;
     match cli.command {
-        MoxenCommand::List => {
-            state.list_contents();
+        MoxenCommand::List { all_flavors } => {
+            for_each_flavor(&mut state, all_flavors, async |state, _| {
+                state.list_contents();
+                Ok(())
+            })
+            .await
+        }
+        MoxenCommand::Info { addon_id } => state.addon_info(addon_id).context("showing addon"),
+        MoxenCommand::Status => {
+            state.addon_status();
             Ok(())
         }
         MoxenCommand::Track { addon_ids } => state
             .track_addons(addon_ids)
             .await
             .context("tracking addons"),
-        MoxenCommand::Update => {
-            state.update_addons().await.context("updating addons")?;
-            Ok(())
+        MoxenCommand::Update { all_flavors } => {
+            for_each_flavor(&mut state, all_flavors, async |state, version| {
+                state
+                    .update_addons()
+                    .await
+                    .with_context(|| format!("updating {version} addons"))?;
+                Ok(())
+            })
+            .await
         }
         MoxenCommand::Switch { registry } => state
             .switch_game_version(registry)
             .context("switching game version"),
         MoxenCommand::ClearCache => state.clear_cache().context("clearing cache"),
-        MoxenCommand::Install => state.install_addons().await.context("installing addons"),
-        MoxenCommand::Uninstall { addon_ids } => state
-            .uninstall_addons(addon_ids)
+        MoxenCommand::Cache { command } => match command {
+            CacheCommand::Info => state.cache_info().context("reading cache info"),
+            CacheCommand::Prune { keep } => state.prune_cache(keep).context("pruning cache"),
+            CacheCommand::Clear { addon_id } => state
+                .clear_addon_cache(addon_id)
+                .context("clearing addon cache"),
+        },
+        MoxenCommand::Install { all_flavors } => {
+            for_each_flavor(&mut state, all_flavors, async |state, version| {
+                state
+                    .install_addons()
+                    .await
+                    .with_context(|| format!("installing {version} addons"))
+            })
+            .await
+        }
+        MoxenCommand::Config { command } => match command {
+            ConfigCommand::Get { key, show_secret } => state
+                .config_get(key, show_secret)
+                .context("reading setting"),
+            ConfigCommand::Set { key, value } => state
+                .config_set(key, value)
+                .await
+                .context("changing setting"),
+            ConfigCommand::Show => state.config_show().context("showing settings"),
+        },
+        MoxenCommand::Profile { command } => match command {
+            ProfileCommand::Add {
+                name,
+                install_dir,
+                flavor,
+                api_key,
+            } => state
+                .add_profile(
+                    name,
+                    Profile {
+                        install_dir: AddonInstallPath(install_dir),
+                        version: flavor,
+                        api_key_source: None,
+                    },
+                    api_key,
+                )
+                .context("adding profile"),
+            ProfileCommand::List => {
+                state.list_profiles();
+                Ok(())
+            }
+            ProfileCommand::Use { name } => state.use_profile(name).context("switching profile"),
+            ProfileCommand::Remove { name } => {
+                state.remove_profile(name).context("removing profile")
+            }
+        },
+        MoxenCommand::Copy {
+            from,
+            to,
+            addon_ids,
+            install,
+        } => state
+            .copy_addons(from, to, addon_ids, install)
+            .await
+            .context("copying addons"),
+        MoxenCommand::Backup { command } => match command {
+            BackupCommand::Create {
+                saved_variables_only,
+            } => state
+                .create_backup(saved_variables_only)
+                .context("creating backup"),
+            BackupCommand::List => state.list_backups().context("listing backups"),
+            BackupCommand::Restore { name } => {
+                state.restore_backup(name).context("restoring backup")
+            }
+        },
+        MoxenCommand::Restore { addon_id } => {
+            state.restore_addon(addon_id).context("restoring addon")
+        }
+        MoxenCommand::Verify { addon_ids } => {
+            state.verify_addons(addon_ids).context("verifying addons")
+        }
+        MoxenCommand::Repair { addon_ids } => state
+            .repair_addons(addon_ids)
+            .await
+            .context("repairing addons"),
+        MoxenCommand::Uninstall {
+            addon_ids,
+            keep_files,
+            keep_cache,
+        } => state
+            .uninstall_addons(addon_ids, keep_files, keep_cache)
             .await
             .context("uninstalling addons"),
-        MoxenCommand::Init => unreachable!("this is covered above"),
+        MoxenCommand::Init { .. } | MoxenCommand::Doctor => {
+            unreachable!("this is covered above")
+        }
     }
 }
+
+/// Run the action for the current game version, or for every version with
+/// tracked addons when `all_flavors` is set
+async fn for_each_flavor(
+    state: &mut MoxenApp,
+    all_flavors: bool,
+    mut action: impl AsyncFnMut(&mut MoxenApp, GameVersion) -> Result<()>,
+) -> Result<()> {
+    for version in state.flavors(all_flavors) {
+        if !state.select_flavor(version)? && all_flavors {
+            continue;
+        }
+        if all_flavors {
+            println!("[{version}]");
+        }
+
+        action(state, version).await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::io::IsTerminal;
+
+const OVERALL_TEMPLATE: &str = "{prefix:>12} [{bar:30}] {pos}/{len} addons";
+const DOWNLOAD_TEMPLATE: &str =
+    "{prefix:>12} [{bar:30}] {bytes}/{total_bytes} ({bytes_per_sec}) {wide_msg}";
+
+/// Progress reporting for downloads.
+///
+/// Bars are only drawn when stdout is a terminal, otherwise messages fall back to
+/// plain `println!` output.
+#[derive(Clone)]
+pub struct Progress {
+    bars: MultiProgress,
+    enabled: bool,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        let enabled = std::io::stdout().is_terminal();
+        let target = if enabled {
+            ProgressDrawTarget::stdout()
+        } else {
+            ProgressDrawTarget::hidden()
+        };
+
+        Self {
+            bars: MultiProgress::with_draw_target(target),
+            enabled,
+        }
+    }
+
+    pub fn overall(&self, total: usize) -> ProgressBar {
+        let bar = self.bars.add(ProgressBar::new(total as u64));
+        bar.set_style(style(OVERALL_TEMPLATE));
+        bar.set_prefix("Total");
+        bar
+    }
+
+    pub fn download(&self, name: &str) -> ProgressBar {
+        let bar = self.bars.add(ProgressBar::new(0));
+        bar.set_style(style(DOWNLOAD_TEMPLATE));
+        bar.set_prefix("Downloading");
+        bar.set_message(name.to_string());
+        bar
+    }
+
+    pub fn println(&self, msg: impl AsRef<str>) {
+        if self.enabled {
+            let _ = self.bars.println(msg.as_ref());
+        } else {
+            println!("{}", msg.as_ref());
+        }
+    }
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn style(template: &str) -> ProgressStyle {
+    ProgressStyle::with_template(template)
+        .expect("progress templates are valid")
+        .progress_chars("=> ")
+}
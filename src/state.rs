@@ -1,66 +1,400 @@
 use anyhow::{Context, Result};
-use std::sync::Arc;
+use indicatif::HumanBytes;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 use tokio::task::JoinSet;
 
 use crate::{
     addon::Addon,
     api::CurseClient,
+    progress::Progress,
     store::{
-        GameVersion, MoxenConfig,
-        path::{MoxenPath, unzip_archive},
+        ConfigKey, DEFAULT_PROFILE, GameVersion, InitOptions, MoxenConfig, Profile, REDACTED,
+        VERSIONS, backup, cache,
+        manifest::{self, InstallManifest},
+        path::{HomeLock, unzip_archive, unzip_archive_filtered, verify_hashes},
         registry::{self, MoxenRegistry},
+        secret, snapshot,
+        toc::{self, Toc},
     },
 };
 
+/// Options that apply to a single invocation of Moxen
+#[derive(Default)]
+pub struct AppOptions {
+    /// Never contact CurseForge, only use the registry and cache
+    pub offline: bool,
+
+    /// Profile to use instead of the active one
+    pub profile: Option<String>,
+
+    /// Game version to use instead of the profile's one
+    pub flavor: Option<GameVersion>,
+}
+
 pub struct MoxenApp {
     config: MoxenConfig,
+    profile: String,
+    version: GameVersion,
     registry: MoxenRegistry,
+    offline: bool,
+    _lock: HomeLock,
 }
 
 impl MoxenApp {
-    pub fn initialise() -> Result<()> {
+    pub fn initialise(options: InitOptions) -> Result<()> {
+        let _lock = HomeLock::acquire().context("locking moxen home")?;
         println!("Initialising Moxen...\n");
-        registry::initialise().context("moxen initialise - registry")?;
-        MoxenConfig::initialise().context("moxen initialise - config")?;
+        registry::initialise(DEFAULT_PROFILE).context("moxen initialise - registry")?;
+        MoxenConfig::initialise(options).context("moxen initialise - config")?;
 
         println!("Successfully initialised Moxen!");
         Ok(())
     }
 
-    pub fn new() -> Result<Self> {
+    pub fn new(options: AppOptions) -> Result<Self> {
+        let lock = HomeLock::acquire().context("locking moxen home")?;
         let config = MoxenConfig::load().context("moxen state creation - config")?;
+        let profile = options
+            .profile
+            .unwrap_or_else(|| config.active_profile.clone());
+
+        let version = match options.flavor {
+            Some(flavor) => flavor,
+            None => config.profile(&profile).context("loading profile")?.version,
+        };
         let registry =
-            registry::load(&config.version).context("moxen state creation - registry")?;
+            registry::load(&profile, &version).context("moxen state creation - registry")?;
 
-        Ok(Self { config, registry })
+        Ok(Self {
+            config,
+            profile,
+            version,
+            registry,
+            offline: options.offline,
+            _lock: lock,
+        })
+    }
+
+    /// Game versions a command should run against
+    pub fn flavors(&self, all: bool) -> Vec<GameVersion> {
+        if all {
+            VERSIONS.to_vec()
+        } else {
+            vec![self.version]
+        }
+    }
+
+    /// Point the app at another game version for the rest of the command.
+    ///
+    /// Returns `false` if there is nothing tracked for that version.
+    pub fn select_flavor(&mut self, version: GameVersion) -> Result<bool> {
+        if version != self.version {
+            self.registry = registry::load(&self.profile, &version)
+                .with_context(|| format!("loading {version} registry"))?;
+            self.version = version;
+        }
+
+        Ok(!self.registry.is_empty())
     }
 
     pub fn switch_game_version(&mut self, version: GameVersion) -> Result<()> {
-        self.config.version = version;
+        self.config.profile_mut(&self.profile)?.version = version;
         self.config.save().context("saving config file")?;
         println!("Switched game version to '{version}'");
 
         Ok(())
     }
 
+    pub fn config_get(&self, key: ConfigKey, show_secret: bool) -> Result<()> {
+        let value = self.config.get(&self.profile, key)?;
+        if key == ConfigKey::ApiKey && !show_secret {
+            eprintln!("The API key is hidden, pass --show-secret to print it");
+            println!("{REDACTED}");
+        } else {
+            println!("{value}");
+        }
+
+        Ok(())
+    }
+
+    pub async fn config_set(&mut self, key: ConfigKey, value: String) -> Result<()> {
+        if key == ConfigKey::ApiKey {
+            if self.offline {
+                eprintln!("Skipping API key check in offline mode");
+            } else {
+                CurseClient::new(value.trim(), self.config.client_options())
+                    .context("creating curseforge client")?
+                    .check_api_key()
+                    .await
+                    .context("the new API key was rejected by CurseForge")?;
+            }
+        }
+
+        self.config.set(&self.profile, key, &value)?;
+        self.config.save().context("saving config file")?;
+        println!("Updated {key}");
+
+        Ok(())
+    }
+
+    pub fn config_show(&self) -> Result<()> {
+        println!("# Using profile '{}' ({})\n", self.profile, self.version);
+        print!("{}", self.config.to_toml()?);
+        Ok(())
+    }
+
+    pub fn add_profile(
+        &mut self,
+        name: String,
+        profile: Profile,
+        api_key: Option<String>,
+    ) -> Result<()> {
+        if !is_valid_profile_name(&name) {
+            anyhow::bail!(
+                "invalid profile name '{name}', use letters, numbers, '-' and '_' only (and not 'cache')"
+            );
+        }
+
+        if self.config.profiles.contains_key(&name) {
+            anyhow::bail!("profile '{name}' already exists");
+        }
+
+        if !profile.install_dir.0.exists() {
+            anyhow::bail!("install directory {} does not exist", profile.install_dir);
+        }
+
+        registry::initialise(&name).context("creating profile registries")?;
+        self.config.profiles.insert(name.clone(), profile);
+        if let Some(key) = api_key {
+            self.config
+                .set_profile_api_key(&name, &key)
+                .context("storing profile API key")?;
+        }
+
+        self.config.save().context("saving config file")?;
+        println!("Added profile '{name}'");
+
+        Ok(())
+    }
+
+    pub fn list_profiles(&self) {
+        println!("Profiles:");
+        for (name, profile) in self.config.profiles.iter() {
+            let marker = if *name == self.config.active_profile {
+                "*"
+            } else {
+                " "
+            };
+
+            println!(
+                "{marker} {name} - {} ({})",
+                profile.install_dir, profile.version
+            );
+        }
+    }
+
+    pub fn use_profile(&mut self, name: String) -> Result<()> {
+        self.config.profile(&name)?;
+        self.config.active_profile = name.clone();
+        self.config.save().context("saving config file")?;
+        println!("Switched to profile '{name}'");
+
+        Ok(())
+    }
+
+    pub fn remove_profile(&mut self, name: String) -> Result<()> {
+        if name == DEFAULT_PROFILE {
+            anyhow::bail!("the default profile can't be removed");
+        }
+
+        if name == self.config.active_profile || name == self.profile {
+            anyhow::bail!("profile '{name}' is in use, switch to another profile first");
+        }
+
+        let Some(profile) = self.config.profiles.remove(&name) else {
+            eprintln!("No such profile: {name}");
+            return Ok(());
+        };
+
+        if let Some(source) = profile.api_key_source {
+            source
+                .delete(&secret::account(Some(&name)))
+                .context("removing profile API key")?;
+        }
+
+        registry::remove(&name).context("removing profile registries")?;
+        manifest::remove_profile(&name).context("removing profile install manifests")?;
+        self.config.save().context("saving config file")?;
+        println!("Removed profile '{name}'");
+
+        Ok(())
+    }
+
     pub fn list_contents(&self) {
         if self.registry.is_empty() {
             println!("No Addons tracked.");
-        } else {
-            println!("Tracked addons:");
-            for (key, addon) in self.registry.iter() {
-                println!("* {} ({}) - {}", addon.name, key, addon.summary);
+            return;
+        }
+
+        println!("Tracked addons:");
+        let tocs = self.toc_reader();
+        for (key, addon) in self.registry.iter() {
+            let report = tocs.report(addon);
+            let local = report
+                .version()
+                .map(|version| format!(" v{version}"))
+                .unwrap_or_default();
+
+            let mut note = String::new();
+            if self.offline && !cache::archive_path(addon).is_ok_and(|f| f.exists()) {
+                note.push_str(" [not cached]");
+            }
+
+            if let Some((built, client)) = report.outdated {
+                note.push_str(&format!(" [out of date: interface {built} < {client}]"));
+            }
+
+            println!(
+                "* {} ({}){local} - {}{note}",
+                addon.name, key, addon.summary
+            );
+
+            if !report.missing.is_empty() {
+                println!("    missing required addons: {}", report.missing.join(", "));
+            }
+        }
+    }
+
+    /// Show a tracked addon along with what its installed `.toc` files say
+    pub fn addon_info(&self, id: i32) -> Result<()> {
+        let Some(addon) = self.registry.get(&id) else {
+            anyhow::bail!("no such addon in {}: {id}", self.version);
+        };
+
+        let report = self.toc_reader().report(addon);
+        println!("{} ({})", addon.name, addon.id);
+        println!("  Summary: {}", addon.summary);
+        println!(
+            "  Tracked file: {} ({})",
+            addon.main_file.file_name, addon.main_file.id
+        );
+        println!(
+            "  Installed version: {}",
+            report.version().unwrap_or("not installed")
+        );
+
+        println!("  Modules:");
+        for module in &addon.main_file.modules {
+            let Some(toc) = report.tocs.iter().find(|(name, _)| name == module) else {
+                println!("  * {module} (not installed)");
+                continue;
+            };
+
+            let toc = &toc.1;
+            let interfaces = toc
+                .interfaces
+                .iter()
+                .map(u32::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+            println!(
+                "  * {module} - {} v{} (interface {interfaces})",
+                toc.title.as_deref().unwrap_or(module),
+                toc.version.as_deref().unwrap_or("?"),
+            );
+        }
+
+        if let Some((built, client)) = report.outdated {
+            println!("  Out of date: built for interface {built}, the client is {client}");
+        }
+        if !report.missing.is_empty() {
+            println!("  Missing required addons: {}", report.missing.join(", "));
+        }
+
+        Ok(())
+    }
+
+    /// Summarise which tracked addons are installed, out of date or missing
+    /// required addons
+    pub fn addon_status(&self) {
+        if self.registry.is_empty() {
+            println!("No Addons tracked.");
+            return;
+        }
+
+        let tocs = self.toc_reader();
+        let (mut not_installed, mut outdated, mut missing_deps) = (0, 0, 0);
+        for addon in self.registry.values() {
+            let report = tocs.report(addon);
+            let mut problems = Vec::new();
+            if report.tocs.is_empty() {
+                not_installed += 1;
+                problems.push("not installed".to_string());
             }
+            if let Some((built, client)) = report.outdated {
+                outdated += 1;
+                problems.push(format!("out of date (interface {built} < {client})"));
+            }
+            if !report.missing.is_empty() {
+                missing_deps += 1;
+                problems.push(format!("missing {}", report.missing.join(", ")));
+            }
+
+            let version = report
+                .version()
+                .map(|version| format!(" v{version}"))
+                .unwrap_or_default();
+            let status = if problems.is_empty() {
+                "ok".to_string()
+            } else {
+                problems.join(", ")
+            };
+            println!("* {} ({}){version}: {status}", addon.name, addon.id);
+        }
+
+        println!(
+            "{} tracked, {not_installed} not installed, {outdated} out of date, {missing_deps} missing required addons",
+            self.registry.len()
+        );
+    }
+
+    fn toc_reader(&self) -> TocReader {
+        let addon_dir = self.install_dir();
+        // Folder names are matched case-insensitively, like the client does
+        let installed = std::fs::read_dir(&addon_dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+                    .map(|entry| entry.file_name().to_string_lossy().to_lowercase())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        TocReader {
+            client: toc::client_interface(&self.active().install_dir.0, &self.version),
+            addon_dir,
+            installed,
+            version: self.version,
         }
     }
 
     pub async fn track_addons(&mut self, mod_ids: Vec<i32>) -> Result<()> {
-        let client = Arc::new(CurseClient::new(&self.config.api_key));
+        let client = self.client()?;
         let mut js = JoinSet::new();
 
+        let version = self.version;
         for mid in mod_ids.into_iter() {
             let client = Arc::clone(&client);
-            js.spawn(async move { client.get_addon(mid).await });
+            let slot = client.acquire_slot().await?;
+            js.spawn(async move {
+                let _slot = slot;
+                client.get_addon(mid, version).await
+            });
         }
 
         while let Some(addon) = js.join_next().await {
@@ -82,8 +416,7 @@ impl MoxenApp {
 
     pub async fn update_addons(&mut self) -> Result<Vec<i32>> {
         println!("Checking for updates...");
-        let mut updated_addons = Vec::new();
-        let client = Arc::new(CurseClient::new(&self.config.api_key));
+        let client = self.client()?;
         let addons = self
             .check_updates(Arc::clone(&client))
             .await
@@ -91,64 +424,173 @@ impl MoxenApp {
 
         if addons.is_empty() {
             println!("No updates required.");
-            return Ok(updated_addons);
+            return Ok(Vec::new());
         }
 
         println!("Updating {} addons...", addons.len());
+        for addon in addons.iter() {
+            self.add_registry_item(addon.clone());
+        }
+
+        let updated_addons = self.download_archives(client, addons).await?;
+
+        self.save().context("saving registry after update")?;
+        println!("Update complete!");
+
+        Ok(updated_addons)
+    }
 
+    /// Download the archives of the given addons into the cache
+    async fn download_archives(
+        &self,
+        client: Arc<CurseClient>,
+        addons: Vec<Addon>,
+    ) -> Result<Vec<i32>> {
+        let mut downloaded = Vec::new();
+        let progress = Progress::new();
+        let overall = progress.overall(addons.len());
         let mut js: JoinSet<Result<i32>> = JoinSet::new();
         for addon in addons {
-            self.add_registry_item(addon.clone());
-
             let client = Arc::clone(&client);
+            let progress = progress.clone();
+            let overall = overall.clone();
+            let slot = client.acquire_slot().await?;
             js.spawn(async move {
-                println!("Updating {}", addon.name);
-                let content = client
-                    .download_addon(&addon)
-                    .await
-                    .with_context(|| format!("downloading latest version of {}", addon.name))?;
+                let _slot = slot;
+                progress.println(format!("Updating {}", addon.name));
 
-                let cache_path = MoxenPath::new()
-                    .context("loading root moxen path")?
-                    .dir("registry")
-                    .context("loading registry path")?
-                    .dir("cache")
-                    .context("loading cache path")?
-                    .dir(&addon.slug)
-                    .with_context(|| format!("loading {} path", addon.slug))?
-                    .build();
+                let filename = cache::archive_path(&addon)?;
 
-                let filename = cache_path.join(addon.main_file.file_name);
-
-                tokio::fs::write(filename, &content)
+                let bar = progress.download(&addon.name);
+                client
+                    .download_addon(&addon, &filename, &bar)
                     .await
-                    .with_context(|| format!("writing out {} to cache", addon.name))?;
+                    .with_context(|| format!("downloading latest version of {}", addon.name))?;
+
+                bar.finish_and_clear();
+                overall.inc(1);
 
                 Ok(addon.id)
             });
         }
 
         while let Some(result) = js.join_next().await {
-            updated_addons.push(
+            downloaded.push(
                 result
                     .context("joining update task")?
                     .context("update task result")?,
             );
         }
 
-        self.save().context("saving registry after update")?;
-        println!("Update complete!");
+        overall.finish_and_clear();
 
-        Ok(updated_addons)
+        Ok(downloaded)
     }
 
     pub async fn install_addons(&mut self) -> Result<()> {
-        let updated_addons = self.update_addons().await.context("updating addons")?;
-        if updated_addons.is_empty() {
+        let to_install = if self.offline {
+            self.cached_addons()
+        } else {
+            self.update_addons().await.context("updating addons")?
+        };
+
+        self.install_ids(to_install).await
+    }
+
+    /// Track addons from one game version in another, resolving the files for the
+    /// target version
+    pub async fn copy_addons(
+        &mut self,
+        from: GameVersion,
+        to: GameVersion,
+        mod_ids: Vec<i32>,
+        install: bool,
+    ) -> Result<()> {
+        if from == to {
+            anyhow::bail!("source and target game versions are the same");
+        }
+
+        let source = registry::load(&self.profile, &from)
+            .with_context(|| format!("loading {from} registry"))?;
+        let mod_ids = if mod_ids.is_empty() {
+            source.keys().copied().collect()
+        } else {
+            mod_ids
+        };
+
+        self.select_flavor(to)?;
+        let client = self.client()?;
+        let mut js: JoinSet<Result<(i32, Option<Addon>)>> = JoinSet::new();
+        for id in mod_ids {
+            let Some(addon) = source.get(&id) else {
+                eprintln!("No such addon in {from}: {id}");
+                continue;
+            };
+            if self.registry.contains_key(&id) {
+                eprintln!(
+                    "\"{}\" ({id}) is already tracked in {to}, skipping",
+                    addon.name
+                );
+                continue;
+            }
+
+            let client = Arc::clone(&client);
+            let slot = client.acquire_slot().await?;
+            js.spawn(async move {
+                let _slot = slot;
+                let addon = client.get_flavor_addon(id, to).await?;
+                Ok((id, addon))
+            });
+        }
+
+        let mut copied = Vec::new();
+        let mut incompatible = Vec::new();
+        while let Some(result) = js.join_next().await {
+            let (id, addon) = result
+                .context("joining copy task")?
+                .context("copy task result")?;
+
+            match addon {
+                Some(addon) => {
+                    println!("Copied \"{}\" ({}) to {to}", addon.name, addon.id);
+                    copied.push(addon.clone());
+                    self.add_registry_item(addon);
+                }
+                None => incompatible.push(&source[&id]),
+            }
+        }
+
+        if !incompatible.is_empty() {
+            eprintln!("The following addons have no build for {to}:");
+            for addon in incompatible {
+                eprintln!("* {} ({})", addon.name, addon.id);
+            }
+        }
+
+        self.save().context("saving copied addons")?;
+
+        if install && !copied.is_empty() {
+            // Only the copied addons, other outdated ones keep matching their installed files
+            let ids = copied.iter().map(|addon| addon.id).collect();
+            self.download_archives(client, copied)
+                .await
+                .context("downloading copied addons")?;
+            self.install_ids(ids)
+                .await
+                .context("installing copied addons")?;
+        }
+
+        Ok(())
+    }
+
+    async fn install_ids(&self, ids: Vec<i32>) -> Result<()> {
+        if ids.is_empty() {
             return Ok(());
         }
 
-        let install_dir = self.config.install_dir.addon_dir(&self.config.version);
+        let install_dir = self.install_dir();
+        let profile = self.profile.clone();
+        let version = self.version;
 
         // Debug install dir for testing
         if cfg!(target_os = "linux") {
@@ -156,9 +598,15 @@ impl MoxenApp {
                 .context("DEBUG - simulating install directory")?;
         }
 
+        if self.config.backup_before_install && self.flavor_dir().join("WTF").is_dir() {
+            let created = backup::create(&self.flavor_dir(), &self.profile, &self.version, None)
+                .context("backing up WTF folder before install")?;
+            println!("Backed up WTF folder as {}", created.name);
+        }
+
         println!("Installing addons...");
         let mut js: JoinSet<Result<()>> = JoinSet::new();
-        for addon in updated_addons {
+        for addon in ids {
             let addon = self
                 .registry
                 .get(&addon)
@@ -166,24 +614,21 @@ impl MoxenApp {
                 .clone();
 
             let install_dir = install_dir.clone();
+            let profile = profile.clone();
 
             js.spawn_blocking(move || {
                 println!("Installing {}...", addon.name);
-                let file = MoxenPath::new()
-                    .context("loading root moxen path")?
-                    .dir("registry")
-                    .context("loading registry path")?
-                    .dir("cache")
-                    .context("loading cache path")?
-                    .dir(&addon.slug)
-                    .with_context(|| format!("loading {} path", addon.slug))?
-                    .file(&addon.main_file.file_name)
-                    .build();
-
-                unzip_archive(&file, &install_dir)
-                    .with_context(|| format!("unzipping {}", file.display()))?;
-
-                Ok(())
+
+                // Reinstalling the same file (e.g. `--offline install`) would
+                // only rotate out useful snapshots
+                let reinstall = manifest::load(&profile, &version, addon.id)?
+                    .is_some_and(|installed| installed.file_id == addon.main_file.id);
+                if !reinstall {
+                    snapshot_addon(&addon, &install_dir, &profile, &version)
+                        .with_context(|| format!("snapshotting {}", addon.name))?;
+                }
+
+                extract_addon(&addon, &install_dir, &profile, &version)
             });
         }
 
@@ -198,79 +643,499 @@ impl MoxenApp {
         Ok(())
     }
 
-    pub async fn uninstall_addons(&mut self, mod_ids: Vec<i32>) -> Result<()> {
-        let mut js: JoinSet<Result<()>> = JoinSet::new();
-        let src_dir = self.config.install_dir.addon_dir(&self.config.version);
+    /// Remove addons from the registry and their module folders from the
+    /// install dir. The registry is only changed once the folders are gone.
+    pub async fn uninstall_addons(
+        &mut self,
+        mod_ids: Vec<i32>,
+        keep_files: bool,
+        keep_cache: bool,
+    ) -> Result<()> {
+        let src_dir = self.install_dir();
 
+        let mut removed = Vec::new();
+        let mut seen = HashSet::new();
         for id in mod_ids {
-            let Some(addon) = self.remove_registry_item(id) else {
+            if !seen.insert(id) {
+                continue;
+            }
+
+            let Some(addon) = self.registry.get(&id) else {
                 eprintln!("No such addon: {}", id);
                 continue;
             };
 
-            let src_dir = src_dir.clone();
-            println!("Removing addon {}...", addon.name);
-            js.spawn(async move {
-                let cache_dir = MoxenPath::new()
-                    .context("loading root moxen path")?
-                    .dir("registry")
-                    .context("loading registry path")?
-                    .dir("cache")
-                    .context("loading cache path")?
-                    .build();
-
-                let addon_dir = cache_dir.join(addon.slug.clone());
-                if addon_dir.exists() {
-                    tokio::fs::remove_dir_all(&addon_dir)
-                        .await
-                        .with_context(|| format!("removing cached dir {}", addon_dir.display()))?;
+            removed.push(addon.clone());
+        }
+
+        // Addons whose folders couldn't be removed stay tracked
+        let mut failed = HashMap::new();
+        if !keep_files {
+            let removing = removed.iter().map(|addon| addon.id).collect();
+            let owners = self.module_owners(&removing);
+
+            // Modules already handled, so addons removed together that share
+            // one don't race to delete it
+            let mut handled = HashSet::new();
+            let mut tasks = HashMap::new();
+            let mut js: JoinSet<(i32, Result<()>)> = JoinSet::new();
+            for addon in removed.iter() {
+                println!("Removing addon {}...", addon.name);
+                if let Err(e) = snapshot_addon(addon, &src_dir, &self.profile, &self.version) {
+                    failed.insert(addon.id, e.context("snapshotting its folders"));
+                    continue;
                 }
 
+                let mut modules = Vec::new();
                 for module in addon.main_file.modules.iter() {
-                    let mod_path = src_dir.join(module);
-                    tokio::fs::remove_dir_all(&mod_path)
-                        .await
-                        .with_context(|| format!("removing module {}", mod_path.display()))?;
+                    if !handled.insert(module.as_str()) {
+                        continue;
+                    }
+
+                    match owners.get(module) {
+                        Some(names) => println!(
+                            "Keeping shared folder {module}, it is still used by {}",
+                            names.join(", ")
+                        ),
+                        None => modules.push(module.clone()),
+                    }
                 }
 
-                Ok(())
-            });
-        }
+                let (id, name) = (addon.id, addon.name.clone());
+                let src_dir = src_dir.clone();
+                let task = js.spawn(async move {
+                    for module in modules.iter() {
+                        let mod_path = src_dir.join(module);
+                        if !mod_path.exists() {
+                            eprintln!("Module {module} of {name} is already gone, skipping");
+                            continue;
+                        }
 
-        while let Some(result) = js.join_next().await {
-            result
-                .context("joining removal task")?
-                .context("removal task result")?;
+                        if let Err(e) = tokio::fs::remove_dir_all(&mod_path).await {
+                            let e = anyhow::Error::new(e)
+                                .context(format!("removing module {}", mod_path.display()));
+                            return (id, Err(e));
+                        }
+                    }
+
+                    (id, Ok(()))
+                });
+                tasks.insert(task.id(), id);
+            }
+
+            while let Some(result) = js.join_next_with_id().await {
+                match result {
+                    Ok((_, (_, Ok(())))) => {}
+                    Ok((_, (id, Err(e)))) => {
+                        failed.insert(id, e);
+                    }
+                    Err(e) => {
+                        failed.insert(tasks[&e.id()], anyhow::Error::new(e));
+                    }
+                }
+            }
         }
 
+        let (removed, kept): (Vec<Addon>, Vec<Addon>) = removed
+            .into_iter()
+            .partition(|addon| !failed.contains_key(&addon.id));
+
+        for addon in removed.iter() {
+            self.remove_registry_item(addon.id);
+        }
         self.save().context("removal - saving registry")?;
-        println!("Successfully removed addons!");
 
+        // An archive another registry might use is only released once they can all be read
+        let refs = if keep_cache {
+            None
+        } else {
+            self.cache_references()
+                .inspect_err(|e| eprintln!("Warning: keeping the cached archives, {e:#}"))
+                .ok()
+        };
+
+        for addon in removed.iter() {
+            manifest::remove(&self.profile, &self.version, addon.id)
+                .context("removing install manifest")?;
+            let Some(refs) = refs.as_ref() else {
+                continue;
+            };
+
+            if !cache::release(addon, refs).context("releasing cached archive")? {
+                println!(
+                    "Keeping cached archive for {}, it is still used by another registry",
+                    addon.name
+                );
+            }
+        }
+
+        if !kept.is_empty() {
+            for addon in kept.iter() {
+                eprintln!(
+                    "Failed to remove {} ({}), it is still tracked: {:#}",
+                    addon.name, addon.id, failed[&addon.id]
+                );
+            }
+            anyhow::bail!("{} addon(s) couldn't be removed", kept.len());
+        }
+
+        if keep_files {
+            println!("Stopped tracking addons, their files were left in place");
+        } else {
+            println!("Successfully removed addons!");
+        }
+
+        Ok(())
+    }
+
+    /// Compare installed addons with the files recorded when they were
+    /// installed, all tracked addons are checked when no IDs are given
+    pub fn verify_addons(&self, mod_ids: Vec<i32>) -> Result<()> {
+        let (broken, outdated) = self.broken_addons(mod_ids, false)?;
+        if !broken.is_empty() {
+            anyhow::bail!(
+                "{} addon(s) need repairing, run `moxen repair`",
+                broken.len()
+            );
+        }
+
+        if outdated > 0 {
+            anyhow::bail!("{outdated} addon(s) don't have the tracked file installed");
+        }
+
+        println!("No broken addons found.");
+        Ok(())
+    }
+
+    /// Re-extract addons whose files were modified or deleted.
+    ///
+    /// Addons installed before manifests were recorded are only reinstalled when
+    /// named explicitly.
+    pub async fn repair_addons(&mut self, mod_ids: Vec<i32>) -> Result<()> {
+        let named = !mod_ids.is_empty();
+        let (broken, outdated) = self.broken_addons(mod_ids, named)?;
+        if broken.is_empty() {
+            println!("Nothing to repair.");
+            if outdated > 0 {
+                anyhow::bail!("{outdated} addon(s) don't have the tracked file installed");
+            }
+            return Ok(());
+        }
+
+        let install_dir = self.install_dir();
+        let progress = Progress::new();
+        let client = if self.offline {
+            None
+        } else {
+            Some(self.client()?)
+        };
+        let mut failed = 0;
+        for addon in broken {
+            let archive = cache::archive_path(&addon)?;
+            let cached = archive.exists()
+                && verify_hashes(&archive, &addon.main_file.hashes).unwrap_or(false);
+
+            if !cached {
+                let Some(client) = client.as_ref() else {
+                    eprintln!(
+                        "Can't repair {}, its archive isn't cached (run without --offline to download it)",
+                        addon.name
+                    );
+                    failed += 1;
+                    continue;
+                };
+
+                let bar = progress.download(&addon.name);
+                client
+                    .download_addon(&addon, &archive, &bar)
+                    .await
+                    .with_context(|| format!("downloading {}", addon.name))?;
+                bar.finish_and_clear();
+            }
+
+            println!("Repairing {}...", addon.name);
+            let install_dir = install_dir.clone();
+            let profile = self.profile.clone();
+            let version = self.version;
+            tokio::task::spawn_blocking(move || {
+                snapshot_addon(&addon, &install_dir, &profile, &version)
+                    .with_context(|| format!("snapshotting {}", addon.name))?;
+                extract_addon(&addon, &install_dir, &profile, &version)
+            })
+            .await
+            .context("joining repair task")??;
+        }
+
+        if failed > 0 {
+            anyhow::bail!("{failed} addon(s) could not be repaired");
+        }
+
+        if outdated > 0 {
+            anyhow::bail!(
+                "repaired the broken addons, but {outdated} addon(s) don't have the tracked file installed"
+            );
+        }
+
+        println!("Repair complete!");
+        Ok(())
+    }
+
+    /// Back up the WTF folder, or only the SavedVariables of tracked addons
+    pub fn create_backup(&self, saved_variables_only: bool) -> Result<()> {
+        let modules: Option<HashSet<String>> = saved_variables_only.then(|| {
+            self.registry
+                .values()
+                .flat_map(|addon| addon.main_file.modules.iter().cloned())
+                .collect()
+        });
+
+        let created = backup::create(
+            &self.flavor_dir(),
+            &self.profile,
+            &self.version,
+            modules.as_ref(),
+        )?;
+        println!(
+            "Created backup {} ({})",
+            created.name,
+            HumanBytes(created.size)
+        );
+
+        Ok(())
+    }
+
+    pub fn list_backups(&self) -> Result<()> {
+        let backups = backup::list(&self.profile, &self.version)?;
+        if backups.is_empty() {
+            println!("No backups for {}/{}.", self.profile, self.version);
+            return Ok(());
+        }
+
+        println!("Backups for {}/{}:", self.profile, self.version);
+        for found in backups {
+            println!("* {} ({})", found.name, HumanBytes(found.size));
+        }
+
+        Ok(())
+    }
+
+    /// Extract a backup over the WTF folder, backing up the current one first so
+    /// the restore can be undone
+    pub fn restore_backup(&self, name: String) -> Result<()> {
+        let Some(found) = backup::find(&self.profile, &self.version, &name)? else {
+            anyhow::bail!("no such backup: {name}");
+        };
+
+        let flavor_dir = self.flavor_dir();
+        if flavor_dir.join("WTF").is_dir() {
+            let current = backup::create(&flavor_dir, &self.profile, &self.version, None)
+                .context("backing up current WTF folder")?;
+            println!("Backed up the current WTF folder as {}", current.name);
+        }
+
+        unzip_archive(&found.path, &flavor_dir)
+            .with_context(|| format!("extracting {}", found.path.display()))?;
+        println!("Restored backup {}", found.name);
+
+        Ok(())
+    }
+
+    /// Put back the module folders from the addon's last snapshot, tracking it
+    /// again if it was uninstalled
+    pub fn restore_addon(&mut self, id: i32) -> Result<()> {
+        let Some(snap) = snapshot::latest(&self.profile, &self.version, id)? else {
+            anyhow::bail!("no snapshot of addon {id}");
+        };
+
+        let install_dir = self.install_dir();
+        let current = self
+            .registry
+            .get(&id)
+            .cloned()
+            .unwrap_or_else(|| snap.info.addon.clone());
+
+        // Snapshot what's there now, so the restore can be undone
+        snapshot_addon(&current, &install_dir, &self.profile, &self.version)
+            .context("snapshotting current folders")?;
+
+        // Folders added since the snapshot would otherwise be left behind
+        let mut modules = installed_modules(&current, &self.profile, &self.version)?;
+        for module in snap.info.modules.iter() {
+            if !modules.contains(module) {
+                modules.push(module.clone());
+            }
+        }
+
+        // Libraries other addons ship are theirs to update
+        let owners = self.module_owners(&HashSet::from([id]));
+        let mut shared = HashSet::new();
+        for module in modules.iter() {
+            if let Some(names) = owners.get(module) {
+                println!(
+                    "Keeping shared folder {module}, it is still used by {}",
+                    names.join(", ")
+                );
+                shared.insert(module.as_str());
+                continue;
+            }
+
+            let dir = install_dir.join(module);
+            if dir.exists() {
+                std::fs::remove_dir_all(&dir)
+                    .with_context(|| format!("removing module {}", dir.display()))?;
+            }
+        }
+
+        let files = unzip_archive_filtered(&snap.path, &install_dir, |path| {
+            path.components()
+                .next()
+                .and_then(|module| module.as_os_str().to_str())
+                .is_none_or(|module| !shared.contains(module))
+        })
+        .with_context(|| format!("extracting {}", snap.path.display()))?;
+
+        let restored = InstallManifest {
+            file_id: snap.info.file_id.unwrap_or(snap.info.addon.main_file.id),
+            files,
+            addon: Some(snap.info.addon.clone()),
+        };
+        manifest::save(&restored, &self.profile, &self.version, id)
+            .context("saving install manifest")?;
+
+        // The registry goes back to the restored release, so it's reported and
+        // updated like any other installed version
+        let name = snap.info.addon.name.clone();
+        let tracked = self.registry.contains_key(&id);
+        self.add_registry_item(snap.info.addon);
+        self.save().context("saving registry")?;
+        if !tracked {
+            println!("Tracking {name} ({id}) again");
+        }
+
+        println!("Restored {name} from snapshot {}", snap.name);
         Ok(())
     }
 
     pub fn clear_cache(&self) -> Result<()> {
-        let cache = MoxenPath::new()
-            .context("loading root moxen path")?
-            .dir("registry")
-            .context("loading registry path")?
-            .dir("cache")
-            .context("loading cache path")?
-            .build();
-
-        std::fs::remove_dir_all(cache).context("removing cache dir")?;
+        for entry in cache::entries().context("reading cache entries")? {
+            cache::remove_entry(&entry)
+                .with_context(|| format!("removing cache entry {}", entry.name))?;
+        }
         println!("Cleared Moxen cache.");
 
         Ok(())
     }
 
+    pub fn cache_info(&self) -> Result<()> {
+        let entries = cache::entries().context("reading cache entries")?;
+        if entries.is_empty() {
+            println!("Cache is empty.");
+            return Ok(());
+        }
+
+        let registries =
+            registry::load_all(self.config.profiles.keys()).context("loading registries")?;
+        let names: HashMap<i32, String> = registries
+            .iter()
+            .flat_map(|(_, _, reg)| reg.values())
+            .map(|addon| (addon.id, format!("{} ({})", addon.name, addon.id)))
+            .collect();
+
+        println!("Cached addons:");
+        let mut total = 0;
+        for entry in entries.iter() {
+            let size = entry.size();
+            total += size;
+            println!(
+                "* {} - {} ({} archives)",
+                entry
+                    .project_id
+                    .and_then(|id| names.get(&id))
+                    .unwrap_or(&entry.name),
+                HumanBytes(size),
+                entry.archives.len()
+            );
+        }
+        println!("Total: {}", HumanBytes(total));
+
+        Ok(())
+    }
+
+    pub fn prune_cache(&self, keep: usize) -> Result<()> {
+        let registries =
+            registry::load_all(self.config.profiles.keys()).context("loading registries")?;
+        let refs = cache::references(registries.iter().map(|(_, _, reg)| reg));
+
+        let mut freed = 0;
+        for entry in cache::entries().context("reading cache entries")? {
+            let Some(project_id) = entry.project_id else {
+                println!("Removing legacy cache entry {}", entry.name);
+                freed += entry.size();
+                cache::remove_entry(&entry)
+                    .with_context(|| format!("removing cache entry {}", entry.name))?;
+                continue;
+            };
+
+            let tracked = refs.keys().any(|(project, _)| *project == project_id);
+            if !tracked {
+                println!("Removing untracked cache entry {}", entry.name);
+                freed += entry.size();
+                cache::remove_entry(&entry)
+                    .with_context(|| format!("removing cache entry {}", entry.name))?;
+                continue;
+            }
+
+            let stale = entry.archives.iter().skip(keep).filter(|archive| {
+                !archive
+                    .file_id
+                    .is_some_and(|file_id| refs.contains_key(&(project_id, file_id)))
+            });
+
+            for archive in stale {
+                println!("Removing {}", archive.path.display());
+                freed += archive.size;
+                std::fs::remove_file(&archive.path)
+                    .with_context(|| format!("removing {}", archive.path.display()))?;
+            }
+        }
+
+        println!("Pruned cache, freed {}.", HumanBytes(freed));
+        Ok(())
+    }
+
+    pub fn clear_addon_cache(&self, id: i32) -> Result<()> {
+        let registries =
+            registry::load_all(self.config.profiles.keys()).context("loading registries")?;
+        let Some(addon) = registries.iter().find_map(|(_, _, reg)| reg.get(&id)) else {
+            eprintln!("No such addon: {id}");
+            return Ok(());
+        };
+
+        let entries = cache::entries().context("reading cache entries")?;
+        if let Some(entry) = entries
+            .iter()
+            .find(|entry| entry.project_id == Some(addon.id))
+        {
+            cache::remove_entry(entry)
+                .with_context(|| format!("removing cache entry {}", entry.name))?;
+        }
+
+        println!("Cleared cache for {}.", addon.name);
+        Ok(())
+    }
+
     async fn check_updates(&self, client: Arc<CurseClient>) -> Result<Vec<Addon>> {
         let mut to_update = Vec::new();
         let mut js: JoinSet<Result<Addon>> = JoinSet::new();
         let addon_ids: Vec<i32> = self.registry.keys().copied().collect();
+        let version = self.version;
         for aid in addon_ids {
             let client = Arc::clone(&client);
-            js.spawn(async move { client.get_addon(aid).await });
+            let slot = client.acquire_slot().await?;
+            js.spawn(async move {
+                let _slot = slot;
+                client.get_addon(aid, version).await
+            });
         }
 
         while let Some(addon) = js.join_next().await {
@@ -283,17 +1148,7 @@ impl MoxenApp {
                 .get(&addon.id)
                 .expect("this has to be present at this point");
 
-            let cache_path = MoxenPath::new()
-                .context("loading root moxen path")?
-                .dir("registry")
-                .context("loading registry path")?
-                .dir("cache")
-                .context("loading cache path")?
-                .dir(&addon.slug)
-                .with_context(|| format!("loading {} path", addon.slug))?
-                .build();
-
-            let filename = cache_path.join(&addon.main_file.file_name);
+            let filename = cache::archive_path(&addon)?;
             if reg_addon.main_file.id != addon.main_file.id || !filename.exists() {
                 to_update.push(addon);
             }
@@ -302,6 +1157,191 @@ impl MoxenApp {
         Ok(to_update)
     }
 
+    /// Tracked addons whose installed files don't match their install manifest,
+    /// reporting what changed. Addons without a manifest count as broken when
+    /// `include_unverified` is set.
+    ///
+    /// Also returns the number of addons with a different file installed than the
+    /// tracked one, those are reported but can't be repaired.
+    fn broken_addons(
+        &self,
+        mod_ids: Vec<i32>,
+        include_unverified: bool,
+    ) -> Result<(Vec<Addon>, usize)> {
+        let mut addons = Vec::new();
+        if mod_ids.is_empty() {
+            addons.extend(self.registry.values());
+            addons.sort_by(|a, b| a.name.cmp(&b.name));
+        } else {
+            for id in mod_ids {
+                match self.registry.get(&id) {
+                    Some(addon) => addons.push(addon),
+                    None => eprintln!("No such addon: {id}"),
+                }
+            }
+        }
+
+        let install_dir = self.install_dir();
+        let mut broken = Vec::new();
+        let mut outdated = 0;
+        for addon in addons {
+            let Some(installed) = manifest::load(&self.profile, &self.version, addon.id)
+                .with_context(|| format!("loading install manifest of {}", addon.name))?
+            else {
+                println!(
+                    "{}: not verified, it was installed without a manifest (`moxen repair {}` reinstalls it)",
+                    addon.name, addon.id
+                );
+                if include_unverified {
+                    broken.push(addon.clone());
+                }
+                continue;
+            };
+
+            if installed.file_id != addon.main_file.id {
+                println!(
+                    "{}: installed file differs from tracked file ({} instead of {}), `moxen --offline install` installs the tracked one",
+                    addon.name, installed.file_id, addon.main_file.id
+                );
+                outdated += 1;
+                continue;
+            }
+
+            let files = installed.check(&install_dir);
+            if files.is_empty() {
+                println!("{}: ok", addon.name);
+                continue;
+            }
+
+            println!("{}: {} file(s) changed", addon.name, files.len());
+            for file in files {
+                println!("  {file}");
+            }
+            broken.push(addon.clone());
+        }
+
+        Ok((broken, outdated))
+    }
+
+    /// Addons in the registry that can be installed without going to the network
+    fn cached_addons(&self) -> Vec<i32> {
+        let mut cached = Vec::new();
+        let mut missing = Vec::new();
+        for addon in self.registry.values() {
+            if cache::archive_path(addon).is_ok_and(|file| file.exists()) {
+                cached.push(addon.id);
+            } else {
+                missing.push(addon);
+            }
+        }
+
+        if !missing.is_empty() {
+            eprintln!("The following addons are not in the cache and can't be installed offline:");
+            for addon in missing {
+                eprintln!(
+                    "* {} ({}) - {}",
+                    addon.name, addon.id, addon.main_file.file_name
+                );
+            }
+        }
+
+        cached
+    }
+
+    /// Cache references across every registry, using the in-memory state for the
+    /// active game version
+    fn cache_references(&self) -> Result<HashMap<cache::CacheKey, usize>> {
+        let registries =
+            registry::load_all(self.config.profiles.keys()).context("loading registries")?;
+        let others = registries
+            .iter()
+            .filter(|(profile, version, _)| *profile != self.profile || *version != self.version())
+            .map(|(_, _, reg)| reg);
+
+        Ok(cache::references(
+            others.chain(std::iter::once(&self.registry)),
+        ))
+    }
+
+    fn client(&self) -> Result<Arc<CurseClient>> {
+        if self.offline {
+            anyhow::bail!("this operation requires network access and can't be run with --offline");
+        }
+
+        let api_key = self
+            .config
+            .api_key_for(&self.profile)
+            .context("loading API key")?;
+        let client = CurseClient::new(&api_key, self.config.client_options())
+            .context("creating curseforge client")?;
+
+        Ok(Arc::new(client))
+    }
+
+    fn active(&self) -> &Profile {
+        self.config
+            .profiles
+            .get(&self.profile)
+            .expect("profile is validated on load")
+    }
+
+    fn version(&self) -> GameVersion {
+        self.version
+    }
+
+    /// Names of the tracked addons claiming each module folder of the install
+    /// dir, leaving out the `excluding` addons of the current registry.
+    ///
+    /// Other registries are only read, one that can't be is skipped with a warning.
+    fn module_owners(&self, excluding: &HashSet<i32>) -> HashMap<String, Vec<String>> {
+        let install_dir = self.install_dir();
+        let mut addons: Vec<Addon> = self
+            .registry
+            .values()
+            .filter(|addon| !excluding.contains(&addon.id))
+            .cloned()
+            .collect();
+
+        // Other profiles and versions can point at the same AddOns dir
+        for (name, profile) in self.config.profiles.iter() {
+            for version in VERSIONS {
+                if (*name == self.profile && version == self.version())
+                    || profile.install_dir.addon_dir(&version) != install_dir
+                {
+                    continue;
+                }
+
+                match registry::validate(name, &version) {
+                    Ok(registry) => addons.extend(registry.into_values()),
+                    Err(e) => eprintln!(
+                        "Warning: can't read the {name}/{version} registry ({e:#}), shared folders it uses may be removed"
+                    ),
+                }
+            }
+        }
+
+        let mut owners: HashMap<String, Vec<String>> = HashMap::new();
+        for addon in addons.iter() {
+            for module in addon.main_file.modules.iter() {
+                let names = owners.entry(module.clone()).or_default();
+                if !names.contains(&addon.name) {
+                    names.push(addon.name.clone());
+                }
+            }
+        }
+
+        owners
+    }
+
+    /// Game version folder in the install dir, e.g. `_retail_`
+    fn flavor_dir(&self) -> PathBuf {
+        self.active().install_dir.0.join(self.version().suffix())
+    }
+
+    fn install_dir(&self) -> PathBuf {
+        self.active().install_dir.addon_dir(&self.version())
+    }
+
     fn add_registry_item(&mut self, addon: Addon) {
         self.registry.insert(addon.id, addon);
     }
@@ -311,6 +1351,144 @@ impl MoxenApp {
     }
 
     fn save(&self) -> Result<()> {
-        registry::save(&self.registry, &self.config.version).context("saving state - registry")
+        registry::save(&self.registry, &self.profile, &self.version())
+            .context("saving state - registry")
     }
 }
+
+/// Reads the installed `.toc` files of tracked addons
+struct TocReader {
+    addon_dir: PathBuf,
+    /// Lowercased names of the folders in the AddOns directory
+    installed: HashSet<String>,
+    /// Interface of the installed client, if it could be read
+    client: Option<u32>,
+    version: GameVersion,
+}
+
+/// What the installed `.toc` files say about a tracked addon
+struct TocReport {
+    /// Installed modules and their TOC
+    tocs: Vec<(String, Toc)>,
+    /// Newest interface the addon was built for and the client's, if it's older
+    outdated: Option<(u32, u32)>,
+    /// Required addons that aren't installed
+    missing: Vec<String>,
+}
+
+impl TocReport {
+    /// Version of the installed addon, from the first module that has one
+    fn version(&self) -> Option<&str> {
+        self.tocs.iter().find_map(|(_, toc)| toc.version.as_deref())
+    }
+}
+
+impl TocReader {
+    fn report(&self, addon: &Addon) -> TocReport {
+        let tocs: Vec<(String, Toc)> = addon
+            .main_file
+            .modules
+            .iter()
+            .filter_map(|module| {
+                let toc = Toc::load(&self.addon_dir.join(module), &self.version)?;
+                Some((module.clone(), toc))
+            })
+            .collect();
+
+        let outdated = self.client.and_then(|client| {
+            let (_, toc) = tocs.iter().find(|(_, toc)| toc.is_outdated(client))?;
+            let built = toc.interfaces.iter().max().copied().unwrap_or_default();
+            Some((built, client))
+        });
+
+        let mut missing: Vec<String> = tocs
+            .iter()
+            .flat_map(|(_, toc)| toc.required_deps.iter())
+            .filter(|dep| {
+                !dep.starts_with("Blizzard_")
+                    && !addon
+                        .main_file
+                        .modules
+                        .iter()
+                        .any(|module| module.eq_ignore_ascii_case(dep))
+                    && !self.installed.contains(&dep.to_lowercase())
+            })
+            .cloned()
+            .collect();
+        missing.sort_unstable_by_key(|dep| dep.to_lowercase());
+        missing.dedup_by(|a, b| a.eq_ignore_ascii_case(b));
+
+        TocReport {
+            tocs,
+            outdated,
+            missing,
+        }
+    }
+}
+
+/// Extract the addon's cached archive and record the files written
+fn extract_addon(
+    addon: &Addon,
+    install_dir: &Path,
+    profile: &str,
+    version: &GameVersion,
+) -> Result<()> {
+    let file = cache::archive_path(addon)?;
+    let files = unzip_archive(&file, install_dir)
+        .with_context(|| format!("unzipping {}", file.display()))?;
+
+    let installed = InstallManifest {
+        file_id: addon.main_file.id,
+        files,
+        addon: Some(addon.clone()),
+    };
+    manifest::save(&installed, profile, version, addon.id).context("saving install manifest")
+}
+
+/// Module folders of the addon, including any the installed version had that
+/// the tracked one doesn't
+fn installed_modules(addon: &Addon, profile: &str, version: &GameVersion) -> Result<Vec<String>> {
+    let installed = manifest::load(profile, version, addon.id)?;
+    let mut modules = addon.main_file.modules.clone();
+    for name in installed
+        .iter()
+        .flat_map(|installed| installed.files.keys())
+    {
+        if let Some((module, _)) = name.split_once('/')
+            && !modules.iter().any(|known| known == module)
+        {
+            modules.push(module.to_string());
+        }
+    }
+
+    Ok(modules)
+}
+
+/// Snapshot the module folders an install or uninstall is about to change,
+/// along with the registry entry of the release that's installed
+fn snapshot_addon(
+    addon: &Addon,
+    install_dir: &Path,
+    profile: &str,
+    version: &GameVersion,
+) -> Result<()> {
+    let modules = installed_modules(addon, profile, version)?;
+    let installed = manifest::load(profile, version, addon.id)?;
+    let file_id = installed.as_ref().map(|installed| installed.file_id);
+    let entry = installed
+        .and_then(|installed| installed.addon)
+        .unwrap_or_else(|| addon.clone());
+
+    snapshot::take(&entry, &modules, file_id, install_dir, profile, version)?;
+
+    Ok(())
+}
+
+/// Profile names double as directory names inside the registry dir
+fn is_valid_profile_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "cache"
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
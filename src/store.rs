@@ -1,17 +1,34 @@
-use std::{io::Write, path::PathBuf};
+use std::{
+    io::{IsTerminal, Write},
+    path::PathBuf,
+    time::Duration,
+};
 
 use anyhow::{Context, Result};
 use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-use crate::addon::Addon;
-use path::MoxenPath;
+use crate::{
+    addon::Addon,
+    api::{self, ClientOptions},
+    detect,
+};
+use path::{MoxenPath, write_atomic};
+use secret::ApiKeySource;
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct AddonInstallPath(pub PathBuf);
 
 impl AddonInstallPath {
+    /// First World of Warcraft install found on this machine
+    pub fn detect() -> Option<Self> {
+        detect::installations()
+            .into_iter()
+            .next()
+            .map(|install| Self(install.root))
+    }
+
     pub fn addon_dir(&self, version: &GameVersion) -> PathBuf {
         self.0
             .join(version.suffix())
@@ -33,12 +50,16 @@ impl Default for AddonInstallPath {
         } else if cfg!(target_os = "macos") {
             Self(PathBuf::from("/Applications/World of Warcraft"))
         } else {
-            Self(MoxenPath::new().expect("this is for testing").build())
+            // Where Wine's default prefix puts the game
+            let home = std::env::var_os("HOME")
+                .map(PathBuf::from)
+                .unwrap_or_default();
+            Self(home.join(".wine/drive_c/Program Files (x86)/World of Warcraft"))
         }
     }
 }
 
-const VERSIONS: [GameVersion; 5] = [
+pub const VERSIONS: [GameVersion; 5] = [
     GameVersion::Retail,
     GameVersion::Beta,
     GameVersion::Ptr,
@@ -58,14 +79,31 @@ pub enum GameVersion {
 }
 
 impl GameVersion {
-    pub fn registry_path(&self) -> Result<PathBuf> {
-        let registry = MoxenPath::new()
-            .context("loading root moxen path")?
-            .dir("registry")
-            .context("loading registry path")?
-            .build();
+    /// Registry file for this version in the given profile.
+    ///
+    /// The default profile keeps its registries at the top of the registry dir,
+    /// other profiles get a sub-directory of their own.
+    pub fn registry_path(&self, profile: &str) -> Result<PathBuf> {
+        let mut registry = MoxenPath::lookup_data()
+            .context("loading moxen data path")?
+            .join("registry");
+
+        if profile != DEFAULT_PROFILE {
+            registry = registry.join(profile);
+        }
+
+        Ok(registry.file(format!("{}.json", self)).build())
+    }
 
-        Ok(registry.join(format!("{}.json", self)))
+    /// CurseForge `gameVersionTypeId`s with builds for this version
+    pub fn game_version_type_ids(&self) -> &'static [i32] {
+        match self {
+            // Beta and PTR run retail builds
+            Self::Retail | Self::Beta | Self::Ptr => &[517],
+            // Progression classic: Mists, Cataclysm and Wrath
+            Self::Classic => &[79434, 77522, 73713],
+            Self::ClassicEra => &[67408],
+        }
     }
 
     pub fn suffix(&self) -> String {
@@ -91,11 +129,304 @@ impl std::fmt::Display for GameVersion {
     }
 }
 
+const DEFAULT_MAX_PARALLEL_DOWNLOADS: usize = 4;
+const DEFAULT_REQUESTS_PER_SECOND: u32 = 5;
+const DEFAULT_DOWNLOAD_RETRIES: u32 = 3;
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_READ_TIMEOUT_SECS: u64 = 30;
+
+/// Upgrades applied to the raw config table, entry `n` migrates schema `n` to `n + 1`
+const CONFIG_MIGRATIONS: [fn(&mut toml::Table); 3] = [
+    config_v0_introduce_version,
+    config_v1_default_profile,
+    config_v2_external_api_keys,
+];
+
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Shown in place of API keys
+pub const REDACTED: &str = "<redacted>";
+
+/// A WoW installation managed by Moxen
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Profile {
+    pub install_dir: AddonInstallPath,
+    pub version: GameVersion,
+
+    /// Where this profile's own API key lives, the global key is used when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_source: Option<ApiKeySource>,
+}
+
+/// Current on-disk schema version of the config
+pub const CONFIG_SCHEMA_VERSION: u32 = CONFIG_MIGRATIONS.len() as u32;
+
 #[derive(Deserialize, Serialize)]
 pub struct MoxenConfig {
-    pub api_key: String,
-    pub version: GameVersion,
-    pub install_dir: AddonInstallPath,
+    #[serde(default)]
+    pub schema_version: u32,
+
+    /// Where the API key is kept, it's never written to this file
+    #[serde(default = "ApiKeySource::preferred")]
+    pub api_key_source: ApiKeySource,
+
+    /// Plain-text key from an old config that `api_key_source` can't store,
+    /// it's moved once a writable source is set
+    #[serde(default, rename = "api_key", skip_serializing_if = "Option::is_none")]
+    unmoved_api_key: Option<String>,
+
+    pub active_profile: String,
+    pub profiles: BTreeMap<String, Profile>,
+
+    #[serde(default = "default_max_parallel_downloads")]
+    pub max_parallel_downloads: usize,
+
+    #[serde(default = "default_requests_per_second")]
+    pub requests_per_second: u32,
+
+    #[serde(default = "default_download_retries")]
+    pub download_retries: u32,
+
+    /// Override for the CurseForge API root (`MOXEN_API_URL`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_base_url: Option<String>,
+
+    /// Override for the download CDN host (`MOXEN_CDN_HOST`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cdn_host: Option<String>,
+
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+
+    #[serde(default = "default_read_timeout_secs")]
+    pub read_timeout_secs: u64,
+
+    /// HTTP(S) or SOCKS proxy URL (`MOXEN_PROXY`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+
+    /// Additional PEM encoded CA certificates to trust
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ca_certificates: Vec<PathBuf>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+
+    /// Back up the WTF folder before installing addons
+    #[serde(default)]
+    pub backup_before_install: bool,
+}
+
+/// Settings that can be read and changed with `moxen config`
+#[derive(Debug, PartialEq, Eq, Copy, Clone, ValueEnum)]
+#[clap(rename_all = "snake_case")]
+pub enum ConfigKey {
+    ApiKey,
+    ApiKeySource,
+    InstallDir,
+    Flavor,
+    MaxParallelDownloads,
+    RequestsPerSecond,
+    DownloadRetries,
+    ApiBaseUrl,
+    CdnHost,
+    ConnectTimeoutSecs,
+    ReadTimeoutSecs,
+    Proxy,
+    CaCertificates,
+    UserAgent,
+    BackupBeforeInstall,
+}
+
+impl std::fmt::Display for ConfigKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = self
+            .to_possible_value()
+            .expect("config keys are never skipped");
+        write!(f, "{}", value.get_name())
+    }
+}
+
+fn parse_positive<T>(value: &str) -> Result<T>
+where
+    T: std::str::FromStr + PartialOrd + Default,
+{
+    let parsed: T = value
+        .parse()
+        .map_err(|_| anyhow::anyhow!("invalid number '{value}'"))?;
+
+    if parsed <= T::default() {
+        anyhow::bail!("'{value}' must be greater than zero");
+    }
+
+    Ok(parsed)
+}
+
+/// Settings provided up front to `moxen init`
+#[derive(Default)]
+pub struct InitOptions {
+    pub api_key: Option<String>,
+    pub api_key_source: Option<ApiKeySource>,
+    pub install_dir: Option<PathBuf>,
+    pub flavor: Option<GameVersion>,
+}
+
+fn prompt_api_key(keep_current: bool) -> Result<String> {
+    let prompt = if keep_current {
+        "Enter Curseforge API Key (leave empty to keep the current key): "
+    } else {
+        "Enter Curseforge API Key: "
+    };
+
+    rpassword::prompt_password(prompt).context("reading password")
+}
+
+fn prompt_install_dir(default_dir: AddonInstallPath) -> Result<AddonInstallPath> {
+    let detected = detect::installations();
+    if !detected.is_empty() {
+        println!("Detected World of Warcraft installs:");
+        for (idx, install) in detected.iter().enumerate() {
+            println!("  {}) {install}", idx + 1);
+        }
+    }
+
+    print!(
+        "Enter World of Warcraft install directory or the number of a detected install (default is \"{}\"): ",
+        default_dir
+    );
+    std::io::stdout().flush().context("flushing stdout")?;
+    let mut install_input = String::new();
+    std::io::stdin()
+        .read_line(&mut install_input)
+        .context("reading user input")?;
+
+    let input = install_input.trim();
+    if input.is_empty() {
+        validate_install_dir(default_dir.0)
+    } else if let Some(install) = input
+        .parse::<usize>()
+        .ok()
+        .and_then(|idx| detected.get(idx.wrapping_sub(1)))
+    {
+        Ok(AddonInstallPath(install.root.clone()))
+    } else {
+        validate_install_dir(PathBuf::from(input))
+    }
+}
+
+pub fn validate_install_dir(dir: PathBuf) -> Result<AddonInstallPath> {
+    if !dir.is_dir() {
+        anyhow::bail!(
+            "World of Warcraft install directory '{}' does not exist",
+            dir.display()
+        );
+    }
+
+    if detect::flavors(&dir).is_empty() {
+        eprintln!(
+            "Warning: no game versions (e.g. _retail_) found in '{}'",
+            dir.display()
+        );
+    }
+
+    Ok(AddonInstallPath(dir))
+}
+
+/// v3 keeps API keys out of the file, plain-text keys are moved into the
+/// `api_key_source` store by [`MoxenConfig::load`] as that can't be done here
+fn config_v2_external_api_keys(_: &mut toml::Table) {}
+
+/// Parse a config of any known schema and migrate it to the current one,
+/// returning the schema version it was written with
+fn migrate_config(content: &str) -> Result<(toml::Table, u32)> {
+    let mut table: toml::Table = toml::from_str(content).context("parsing config")?;
+
+    let from = table
+        .get("schema_version")
+        .and_then(toml::Value::as_integer)
+        .map_or(0, |version| version as u32);
+
+    if from > CONFIG_SCHEMA_VERSION {
+        anyhow::bail!(
+            "config schema v{from} is newer than this version of moxen supports (v{CONFIG_SCHEMA_VERSION})"
+        );
+    }
+
+    for migration in CONFIG_MIGRATIONS.iter().skip(from as usize) {
+        migration(&mut table);
+    }
+
+    Ok((table, from))
+}
+
+/// Remove plain-text API keys, returning them with the profile they belong to
+fn take_plaintext_keys(table: &mut toml::Table) -> Vec<(Option<String>, String)> {
+    let mut keys = Vec::new();
+    if let Some(toml::Value::String(key)) = table.remove("api_key") {
+        keys.push((None, key));
+    }
+
+    if let Some(profiles) = table
+        .get_mut("profiles")
+        .and_then(toml::Value::as_table_mut)
+    {
+        for (name, profile) in profiles.iter_mut() {
+            if let Some(toml::Value::String(key)) = profile
+                .as_table_mut()
+                .and_then(|profile| profile.remove("api_key"))
+            {
+                keys.push((Some(name.clone()), key));
+            }
+        }
+    }
+
+    keys
+}
+
+/// v1 only introduced `schema_version`, new settings are covered by serde defaults
+fn config_v0_introduce_version(_: &mut toml::Table) {}
+
+/// v2 moved the install dir and game version into the `default` profile
+fn config_v1_default_profile(table: &mut toml::Table) {
+    let mut profile = toml::Table::new();
+    for key in ["install_dir", "version"] {
+        if let Some(value) = table.remove(key) {
+            profile.insert(key.to_string(), value);
+        }
+    }
+
+    let mut profiles = toml::Table::new();
+    profiles.insert(DEFAULT_PROFILE.to_string(), profile.into());
+    table.insert("profiles".to_string(), profiles.into());
+    table.insert("active_profile".to_string(), DEFAULT_PROFILE.into());
+}
+
+fn default_max_parallel_downloads() -> usize {
+    DEFAULT_MAX_PARALLEL_DOWNLOADS
+}
+
+fn default_requests_per_second() -> u32 {
+    DEFAULT_REQUESTS_PER_SECOND
+}
+
+fn default_download_retries() -> u32 {
+    DEFAULT_DOWNLOAD_RETRIES
+}
+
+fn default_connect_timeout_secs() -> u64 {
+    DEFAULT_CONNECT_TIMEOUT_SECS
+}
+
+fn default_read_timeout_secs() -> u64 {
+    DEFAULT_READ_TIMEOUT_SECS
+}
+
+/// Environment variables take precedence over values from the config file
+fn setting_override(var: &str, configured: &Option<String>) -> Option<String> {
+    std::env::var(var)
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+        .or_else(|| configured.clone())
 }
 
 impl MoxenConfig {
@@ -107,7 +438,7 @@ impl MoxenConfig {
 
         let registries_exist = VERSIONS.iter().all(|version| {
             let path = version
-                .registry_path()
+                .registry_path(DEFAULT_PROFILE)
                 .context("loading registry path")
                 .expect("error loading path");
 
@@ -117,180 +448,2396 @@ impl MoxenConfig {
         Ok(cfg_path.exists() && registries_exist)
     }
 
-    pub fn initialise() -> Result<()> {
+    /// Create the config, or update the given settings if it already exists.
+    ///
+    /// Settings that aren't provided are prompted for when stdin is a terminal.
+    pub fn initialise(options: InitOptions) -> Result<()> {
         let cfg_path = MoxenPath::new()
             .context("loading root moxen path")?
             .file("config.toml")
             .build();
 
-        let api_key =
-            rpassword::prompt_password("Enter Curseforge API Key: ").context("reading password")?;
+        let interactive = std::io::stdin().is_terminal();
+        if cfg_path.exists() {
+            return Self::reinitialise(options, interactive);
+        }
 
-        print!(
-            "Enter World of Warcraft install directory (default is \"{}\"): ",
-            AddonInstallPath::default()
-        );
-        std::io::stdout().flush().context("flushing stdout")?;
-        let mut install_input = String::new();
-        std::io::stdin()
-            .read_line(&mut install_input)
-            .context("reading user input")?;
-
-        let install_dir = if install_input.trim().is_empty() {
-            AddonInstallPath::default()
-        } else {
-            AddonInstallPath(PathBuf::from(install_input.trim()))
+        let api_key_source = options
+            .api_key_source
+            .unwrap_or_else(ApiKeySource::preferred);
+
+        let api_key = match options.api_key {
+            _ if !api_key_source.is_writable() => None,
+            Some(key) => Some(key),
+            None if interactive => Some(prompt_api_key(false)?),
+            None => anyhow::bail!(
+                "no API key provided, pass --api-key or --api-key-env, or set MOXEN_API_KEY"
+            ),
+        };
+
+        let detected_dir = || AddonInstallPath::detect().unwrap_or_default();
+
+        let install_dir = match options.install_dir {
+            Some(dir) => validate_install_dir(dir)?,
+            None if interactive => prompt_install_dir(detected_dir())?,
+            None => AddonInstallPath::detect().context(
+                "no World of Warcraft install found, pass --install-dir or set MOXEN_INSTALL_DIR",
+            )?,
         };
 
-        assert!(install_dir.0.exists());
-        let cfg = MoxenConfig {
-            api_key,
-            version: GameVersion::default(),
+        let profile = Profile {
+            version: options
+                .flavor
+                .unwrap_or_else(|| detect::preferred_flavor(&install_dir.0)),
             install_dir,
+            api_key_source: None,
         };
 
-        let content = toml::to_string_pretty(&cfg).context("serialising config")?;
-        std::fs::write(&cfg_path, &content).context("writing out config file")?;
+        let mut cfg = MoxenConfig::new(api_key_source, profile);
+        match api_key {
+            Some(key) => cfg.set(DEFAULT_PROFILE, ConfigKey::ApiKey, &key)?,
+            None => {
+                cfg.api_key_for(DEFAULT_PROFILE)?;
+            }
+        }
 
-        Ok(())
+        cfg.save().context("writing out config file")
     }
 
-    pub fn load() -> Result<Self> {
-        let cfg_path = MoxenPath::new()
-            .context("loading root moxen path")?
-            .file("config.toml")
-            .build();
-        let content = std::fs::read_to_string(&cfg_path).context("reading config file")?;
-        toml::from_str(&content).context("deserialising config")
+    /// Update settings of an existing config for the active profile, leaving
+    /// everything else alone
+    fn reinitialise(options: InitOptions, interactive: bool) -> Result<()> {
+        let mut cfg = Self::load().context("loading existing config")?;
+        let prompt = interactive
+            && options.api_key.is_none()
+            && options.api_key_source.is_none()
+            && options.install_dir.is_none()
+            && options.flavor.is_none();
+
+        let active = cfg.active_profile.clone();
+        match options.api_key_source {
+            // The stored key is about to be replaced, so there's nothing to move
+            Some(source) if options.api_key.is_some() => {
+                match cfg.profile_mut(&active)?.api_key_source {
+                    Some(ref mut current) => *current = source,
+                    None => cfg.api_key_source = source,
+                }
+            }
+            Some(source) => cfg.set(&active, ConfigKey::ApiKeySource, &source.to_string())?,
+            None => {}
+        }
+
+        if let Some(key) = options.api_key {
+            cfg.set(&active, ConfigKey::ApiKey, &key)?;
+        } else if prompt && cfg.key_source(&active).0.is_writable() {
+            let key = prompt_api_key(true)?;
+            if !key.trim().is_empty() {
+                cfg.set(&active, ConfigKey::ApiKey, &key)?;
+            }
+        }
+
+        let profile = cfg.profile_mut(&active)?;
+        if let Some(dir) = options.install_dir {
+            profile.install_dir = validate_install_dir(dir)?;
+        } else if prompt {
+            profile.install_dir = prompt_install_dir(profile.install_dir.clone())?;
+        }
+
+        if let Some(flavor) = options.flavor {
+            profile.version = flavor;
+        }
+
+        cfg.save().context("saving config")
     }
 
-    pub fn save(&self) -> Result<()> {
-        let cfg_path = MoxenPath::new()
-            .context("loading root moxen path")?
-            .file("config.toml")
-            .build();
-        let content = toml::to_string_pretty(&self).context("serialising config")?;
-        std::fs::write(&cfg_path, &content).context("writing out config file")?;
+    /// Fresh config with `profile` as the default profile
+    pub fn new(api_key_source: ApiKeySource, profile: Profile) -> Self {
+        Self {
+            schema_version: CONFIG_SCHEMA_VERSION,
+            api_key_source,
+            unmoved_api_key: None,
+            active_profile: DEFAULT_PROFILE.to_string(),
+            profiles: BTreeMap::from([(DEFAULT_PROFILE.to_string(), profile)]),
+            max_parallel_downloads: DEFAULT_MAX_PARALLEL_DOWNLOADS,
+            requests_per_second: DEFAULT_REQUESTS_PER_SECOND,
+            download_retries: DEFAULT_DOWNLOAD_RETRIES,
+            api_base_url: None,
+            cdn_host: None,
+            connect_timeout_secs: DEFAULT_CONNECT_TIMEOUT_SECS,
+            read_timeout_secs: DEFAULT_READ_TIMEOUT_SECS,
+            proxy: None,
+            ca_certificates: Vec::new(),
+            user_agent: None,
+            backup_before_install: false,
+        }
+    }
+
+    /// Current value of a setting, profile settings are read from `profile`
+    pub fn get(&self, profile: &str, key: ConfigKey) -> Result<String> {
+        let profile_cfg = self.profile(profile)?;
+        let value = match key {
+            ConfigKey::ApiKey => self.api_key_for(profile)?,
+            ConfigKey::ApiKeySource => self.key_source(profile).0.to_string(),
+            ConfigKey::InstallDir => profile_cfg.install_dir.to_string(),
+            ConfigKey::Flavor => profile_cfg.version.to_string(),
+            ConfigKey::MaxParallelDownloads => self.max_parallel_downloads.to_string(),
+            ConfigKey::RequestsPerSecond => self.requests_per_second.to_string(),
+            ConfigKey::DownloadRetries => self.download_retries.to_string(),
+            ConfigKey::ApiBaseUrl => self.api_base_url.clone().unwrap_or_default(),
+            ConfigKey::CdnHost => self.cdn_host.clone().unwrap_or_default(),
+            ConfigKey::ConnectTimeoutSecs => self.connect_timeout_secs.to_string(),
+            ConfigKey::ReadTimeoutSecs => self.read_timeout_secs.to_string(),
+            ConfigKey::Proxy => self.proxy.clone().unwrap_or_default(),
+            ConfigKey::CaCertificates => self
+                .ca_certificates
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+            ConfigKey::UserAgent => self.user_agent.clone().unwrap_or_default(),
+            ConfigKey::BackupBeforeInstall => self.backup_before_install.to_string(),
+        };
+
+        Ok(value)
+    }
+
+    /// Validate and update a setting, profile settings are written to `profile`.
+    ///
+    /// An empty value clears optional settings. API keys are written straight to
+    /// their store, everything else needs a [`Self::save`].
+    pub fn set(&mut self, profile: &str, key: ConfigKey, value: &str) -> Result<()> {
+        let value = value.trim();
+        let optional = || (!value.is_empty()).then(|| value.to_string());
+
+        match key {
+            ConfigKey::ApiKey => {
+                if value.is_empty() {
+                    anyhow::bail!("the API key can't be empty");
+                }
+
+                let (source, account) = self.key_source(profile);
+                source.write(&account, value)?;
+            }
+            ConfigKey::ApiKeySource => {
+                let new: ApiKeySource = value.parse()?;
+                let (current, account) = self.key_source(profile);
+                if new == *current {
+                    return Ok(());
+                }
+
+                // Stored keys follow the setting, read only sources leave them be
+                if let Some(key) = self.unmoved_api_key.as_deref()
+                    && account == secret::account(None)
+                    && new.is_writable()
+                {
+                    new.write(&account, key)?;
+                    self.unmoved_api_key = None;
+                    eprintln!("Moved the plain-text API key out of config.toml");
+                } else if current.is_writable() && new.is_writable() {
+                    let key = current.read(&account)?;
+                    new.write(&account, &key)?;
+                    current.delete(&account)?;
+                } else if let Err(e) = new.read(&account) {
+                    eprintln!("Warning: {e:#}");
+                }
+
+                match self.profile_mut(profile)?.api_key_source {
+                    Some(ref mut source) => *source = new,
+                    None => self.api_key_source = new,
+                }
+            }
+            ConfigKey::InstallDir => {
+                self.profile_mut(profile)?.install_dir =
+                    validate_install_dir(PathBuf::from(value))?;
+            }
+            ConfigKey::Flavor => {
+                self.profile_mut(profile)?.version = GameVersion::from_str(value, true)
+                    .map_err(|e| anyhow::anyhow!("invalid flavor '{value}': {e}"))?;
+            }
+            ConfigKey::MaxParallelDownloads => {
+                self.max_parallel_downloads = parse_positive(value)?;
+            }
+            ConfigKey::RequestsPerSecond => self.requests_per_second = parse_positive(value)?,
+            ConfigKey::DownloadRetries => {
+                self.download_retries = value
+                    .parse()
+                    .with_context(|| format!("invalid number '{value}'"))?;
+            }
+            ConfigKey::ApiBaseUrl => self.api_base_url = optional(),
+            ConfigKey::CdnHost => self.cdn_host = optional(),
+            ConfigKey::ConnectTimeoutSecs => self.connect_timeout_secs = parse_positive(value)?,
+            ConfigKey::ReadTimeoutSecs => self.read_timeout_secs = parse_positive(value)?,
+            ConfigKey::Proxy => self.proxy = optional(),
+            ConfigKey::CaCertificates => {
+                let paths: Vec<PathBuf> = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|path| !path.is_empty())
+                    .map(PathBuf::from)
+                    .collect();
+
+                if let Some(missing) = paths.iter().find(|path| !path.is_file()) {
+                    anyhow::bail!("CA certificate '{}' does not exist", missing.display());
+                }
+
+                self.ca_certificates = paths;
+            }
+            ConfigKey::UserAgent => self.user_agent = optional(),
+            ConfigKey::BackupBeforeInstall => {
+                self.backup_before_install = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("'{value}' must be true or false"))?;
+            }
+        }
 
         Ok(())
     }
-}
 
-pub mod path {
-    use anyhow::{Context, Result};
-    use std::path::{Path, PathBuf};
-    use zip::ZipArchive;
+    /// The config as it's written to disk
+    pub fn to_toml(&self) -> Result<String> {
+        let mut table = toml::Table::try_from(self).context("serialising config")?;
+        if let Some(key) = table.get_mut("api_key") {
+            *key = toml::Value::String(REDACTED.to_string());
+        }
 
-    pub struct MoxenPath {
-        path: PathBuf,
+        toml::to_string_pretty(&table).context("serialising config")
     }
 
-    impl MoxenPath {
-        pub fn new() -> Result<Self> {
-            let Some(root) = dotstore::home_store("moxen").context("initialising home store")?
-            else {
-                eprintln!("unable to get path to home directory");
-                anyhow::bail!("error getting home directory path");
-            };
+    pub fn profile(&self, name: &str) -> Result<&Profile> {
+        self.profiles
+            .get(name)
+            .with_context(|| format!("no such profile '{name}'"))
+    }
+
+    pub fn profile_mut(&mut self, name: &str) -> Result<&mut Profile> {
+        self.profiles
+            .get_mut(name)
+            .with_context(|| format!("no such profile '{name}'"))
+    }
+
+    /// Where the profile's API key is kept and the name it's stored under,
+    /// falling back to the global key
+    pub fn key_source(&self, name: &str) -> (&ApiKeySource, String) {
+        match self
+            .profiles
+            .get(name)
+            .and_then(|profile| profile.api_key_source.as_ref())
+        {
+            Some(source) => (source, secret::account(Some(name))),
+            None => (&self.api_key_source, secret::account(None)),
+        }
+    }
 
-            Ok(Self { path: root })
+    /// API key to use for the profile, falling back to the global key
+    pub fn api_key_for(&self, name: &str) -> Result<String> {
+        let (source, account) = self.key_source(name);
+        match (source.read(&account), &self.unmoved_api_key) {
+            (Err(_), Some(key)) if account == secret::account(None) => Ok(key.clone()),
+            (result, _) => result,
         }
+    }
 
-        pub fn dir(mut self, dir: impl AsRef<Path>) -> Result<Self> {
-            self.path = self.path.join(dir);
-            if !self.path.exists() {
-                std::fs::create_dir_all(&self.path)
-                    .with_context(|| format!("creating directory '{}'", self.path.display()))?;
+    /// Give a profile its own API key, stored like the global one if possible
+    pub fn set_profile_api_key(&mut self, name: &str, key: &str) -> Result<()> {
+        let source = if self.api_key_source.is_writable() {
+            self.api_key_source.clone()
+        } else {
+            ApiKeySource::preferred()
+        };
+
+        self.profile_mut(name)?.api_key_source = Some(source);
+        self.set(name, ConfigKey::ApiKey, key)
+    }
+
+    /// Move API keys found in an old config file into their store, keeping the
+    /// global key in the file if its source is read only.
+    ///
+    /// Returns whether any key was moved.
+    fn store_plaintext_keys(&mut self, keys: Vec<(Option<String>, String)>) -> Result<bool> {
+        let mut moved = false;
+        for (profile, key) in keys {
+            match profile {
+                Some(name) => self.set_profile_api_key(&name, &key)?,
+                None if self.api_key_source.is_writable() => {
+                    self.api_key_source.write(&secret::account(None), &key)?
+                }
+                None => {
+                    self.unmoved_api_key = Some(key);
+                    continue;
+                }
             }
 
-            Ok(self)
+            moved = true;
         }
 
-        pub fn file(mut self, filename: impl AsRef<Path>) -> Self {
-            self.path = self.path.join(filename);
-            self
+        if moved {
+            eprintln!(
+                "Moved plain-text API keys out of config.toml (api_key_source = \"{}\")",
+                self.api_key_source
+            );
         }
 
-        pub fn build(self) -> PathBuf {
-            self.path
-        }
+        Ok(moved)
     }
 
-    pub fn unzip_archive(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<()> {
-        let file = std::fs::File::open(&src)
-            .with_context(|| format!("opening zip archive: {}", src.as_ref().display()))?;
+    pub fn client_options(&self) -> ClientOptions {
+        ClientOptions {
+            max_parallel_downloads: self.max_parallel_downloads.max(1),
+            requests_per_second: self.requests_per_second.max(1),
+            download_retries: self.download_retries,
+            base_url: setting_override("MOXEN_API_URL", &self.api_base_url)
+                .unwrap_or_else(|| api::DEFAULT_BASE_URL.to_string()),
+            cdn_host: setting_override("MOXEN_CDN_HOST", &self.cdn_host)
+                .unwrap_or_else(|| api::DEFAULT_CDN_HOST.to_string()),
+            connect_timeout: Duration::from_secs(self.connect_timeout_secs),
+            read_timeout: Duration::from_secs(self.read_timeout_secs),
+            proxy: setting_override("MOXEN_PROXY", &self.proxy),
+            ca_certificates: self.ca_certificates.clone(),
+            user_agent: self
+                .user_agent
+                .clone()
+                .unwrap_or_else(|| format!("moxen/{}", env!("CARGO_PKG_VERSION"))),
+        }
+    }
 
-        let mut archive = ZipArchive::new(file).context("initialising ZipArchive")?;
+    pub fn load() -> Result<Self> {
+        let (mut table, from) = Self::read()?;
+        let plaintext = take_plaintext_keys(&mut table);
+        let mut cfg: Self = table.try_into().context("deserialising config")?;
+        cfg.validate()?;
+        let moved = cfg
+            .store_plaintext_keys(plaintext)
+            .context("moving API keys out of the config")?;
 
-        for idx in 0..archive.len() {
-            let mut entry = archive.by_index(idx).context("retrieving zip entry")?;
-            let Some(entry_path) = entry.enclosed_name() else {
-                continue;
-            };
+        let migrating = from != CONFIG_SCHEMA_VERSION;
+        // A kept key stays in the file as is, so this is only reported once
+        if migrating && cfg.unmoved_api_key.is_some() {
+            eprintln!(
+                "Warning: the plain-text API key was kept in config.toml, api_key_source \"{}\" can't store it. Move it with `moxen config set api_key_source keyring` (or `file`)",
+                cfg.api_key_source
+            );
+        }
 
-            let dst_path = dst.as_ref().join(entry_path);
+        if migrating || moved {
+            cfg.schema_version = CONFIG_SCHEMA_VERSION;
+            cfg.save().context("saving migrated config")?;
+        }
 
-            if entry.is_dir() {
-                std::fs::create_dir_all(&dst_path).with_context(|| {
-                    format!("creating sub-dir for zip entry - {}", dst_path.display())
-                })?;
-            } else {
-                if let Some(parent) = dst_path.parent() {
-                    std::fs::create_dir_all(parent).with_context(|| {
-                        format!(
-                            "creating parent directory {} for {}",
-                            parent.display(),
-                            dst_path.display()
-                        )
-                    })?;
-                }
+        Ok(cfg)
+    }
 
-                let mut output = std::fs::File::create(&dst_path)
-                    .with_context(|| format!("creating file {}", dst_path.display()))?;
+    /// Check the settings `set` validates, as a hand-edited file may not match
+    fn validate(&self) -> Result<()> {
+        let positive = [
+            (
+                ConfigKey::MaxParallelDownloads,
+                self.max_parallel_downloads as u64,
+            ),
+            (
+                ConfigKey::RequestsPerSecond,
+                self.requests_per_second.into(),
+            ),
+            (ConfigKey::ConnectTimeoutSecs, self.connect_timeout_secs),
+            (ConfigKey::ReadTimeoutSecs, self.read_timeout_secs),
+        ];
 
-                std::io::copy(&mut entry, &mut output).with_context(|| {
-                    format!(
-                        "copying {:?} to {}",
-                        entry.enclosed_name(),
-                        dst_path.display()
-                    )
-                })?;
+        for (key, value) in positive {
+            if value == 0 {
+                anyhow::bail!("{key} in config.toml must be greater than zero");
             }
         }
 
         Ok(())
     }
-}
 
-pub mod registry {
-    use super::*;
+    /// Load the config without migrating it or moving API keys, also returning
+    /// whether [`Self::load`] would rewrite the file
+    pub fn load_read_only() -> Result<(Self, bool)> {
+        let (mut table, from) = Self::read()?;
+        let plaintext = take_plaintext_keys(&mut table);
+        let mut cfg: Self = table.try_into().context("deserialising config")?;
+        cfg.validate()?;
 
-    pub type MoxenRegistry = HashMap<i32, Addon>;
+        let needs_migration = from != CONFIG_SCHEMA_VERSION
+            || plaintext
+                .iter()
+                .any(|(profile, _)| profile.is_some() || cfg.api_key_source.is_writable());
 
-    pub fn initialise() -> Result<()> {
-        for version in VERSIONS {
-            let reg = MoxenRegistry::new();
-            let registry_path = version.registry_path().context("registry path")?;
-            let registry = serde_json::to_string_pretty(&reg).context("serialising registry")?;
-            std::fs::write(&registry_path, &registry).context("writing new registry")?;
-        }
+        // Keys that haven't been moved yet are still used
+        cfg.unmoved_api_key = plaintext
+            .into_iter()
+            .find_map(|(profile, key)| profile.is_none().then_some(key));
 
-        Ok(())
+        Ok((cfg, needs_migration))
     }
 
-    pub fn load(version: &GameVersion) -> Result<MoxenRegistry> {
-        let registry_path = version.registry_path().context("registry path")?;
-        let content = std::fs::read_to_string(&registry_path).context("reading registry file")?;
-        let registry = serde_json::from_str(&content).context("deserialising registry")?;
+    /// Parse the config file and apply the schema migrations in memory,
+    /// returning the schema version it was written with
+    fn read() -> Result<(toml::Table, u32)> {
+        let cfg_path = MoxenPath::lookup_config()
+            .context("loading root moxen path")?
+            .file("config.toml")
+            .build();
+        let content = std::fs::read_to_string(&cfg_path).context("reading config file")?;
 
-        Ok(registry)
+        migrate_config(&content)
     }
 
-    pub fn save(registry: &MoxenRegistry, version: &GameVersion) -> Result<()> {
-        let registry_path = version.registry_path().context("registry path")?;
-        let contents = serde_json::to_string_pretty(registry).context("serialising registry")?;
-        std::fs::write(&registry_path, &contents).context("saving registry to disk")?;
+    pub fn save(&self) -> Result<()> {
+        let cfg_path = MoxenPath::new()
+            .context("loading root moxen path")?
+            .file("config.toml")
+            .build();
+        let content = toml::to_string_pretty(&self).context("serialising config")?;
+        write_atomic(&cfg_path, &content).context("writing out config file")?;
+
+        Ok(())
+    }
+}
+
+pub mod path {
+    use anyhow::{Context, Result};
+    use md5::Md5;
+    use sha1::{Digest, Sha1};
+    use std::{
+        collections::BTreeMap,
+        fs::{File, TryLockError},
+        io::{Read, Write},
+        path::{Path, PathBuf},
+        sync::OnceLock,
+    };
+    use zip::{CompressionMethod, ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+    /// Separate location for cached archives, e.g. on another disk
+    const CACHE_DIR_VAR: &str = "MOXEN_CACHE_DIR";
+
+    /// Home set with `--home` or `MOXEN_HOME`
+    static HOME_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+    /// Keep everything under `home` instead of the default locations.
+    ///
+    /// Has to be called before any path is resolved.
+    pub fn set_home(home: PathBuf) {
+        let _ = HOME_OVERRIDE.set(home);
+    }
+
+    /// Where the config, registries and cached archives are kept
+    struct MoxenDirs {
+        config: PathBuf,
+        data: PathBuf,
+        cache: PathBuf,
+    }
+
+    impl MoxenDirs {
+        fn resolve() -> Result<Self> {
+            let mut dirs = match HOME_OVERRIDE.get() {
+                Some(home) => Self::single(home.clone()),
+                None => Self::platform()?,
+            };
+
+            // The variable may point at a shared location such as another disk
+            if let Some(cache) = std::env::var_os(CACHE_DIR_VAR).filter(|dir| !dir.is_empty()) {
+                dirs.cache = PathBuf::from(cache).join("moxen");
+            }
+
+            Ok(dirs)
+        }
+
+        /// Everything under one directory, laid out like `~/.moxen`
+        fn single(root: PathBuf) -> Self {
+            Self {
+                cache: root.join("registry").join("cache"),
+                data: root.clone(),
+                config: root,
+            }
+        }
+
+        #[cfg(target_os = "linux")]
+        fn platform() -> Result<Self> {
+            let Some(home) = std::env::home_dir() else {
+                eprintln!("unable to get path to home directory");
+                anyhow::bail!("error getting home directory path");
+            };
+
+            // Homes created before XDG support stay where they are
+            let legacy = home.join(".moxen");
+            if legacy.is_dir() {
+                return Ok(Self::single(legacy));
+            }
+
+            let xdg = |var: &str, fallback: &str| {
+                std::env::var_os(var)
+                    .map(PathBuf::from)
+                    .filter(|dir| dir.is_absolute())
+                    .unwrap_or_else(|| home.join(fallback))
+                    .join("moxen")
+            };
+
+            Ok(Self {
+                config: xdg("XDG_CONFIG_HOME", ".config"),
+                data: xdg("XDG_DATA_HOME", ".local/share"),
+                cache: xdg("XDG_CACHE_HOME", ".cache"),
+            })
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        fn platform() -> Result<Self> {
+            let Some(root) = dotstore::home_store("moxen").context("initialising home store")?
+            else {
+                eprintln!("unable to get path to home directory");
+                anyhow::bail!("error getting home directory path");
+            };
+
+            Ok(Self::single(root))
+        }
+    }
+
+    pub struct MoxenPath {
+        path: PathBuf,
+    }
+
+    impl MoxenPath {
+        /// Directory holding the config, API key files and lock
+        pub fn new() -> Result<Self> {
+            Self::at(MoxenDirs::resolve()?.config)
+        }
+
+        /// Directory holding the registries
+        pub fn data() -> Result<Self> {
+            Self::at(MoxenDirs::resolve()?.data)
+        }
+
+        /// Directory holding cached archives
+        pub fn cache() -> Result<Self> {
+            Self::at(MoxenDirs::resolve()?.cache)
+        }
+
+        /// Directory holding the config, without creating it
+        pub fn lookup_config() -> Result<Self> {
+            Ok(Self {
+                path: MoxenDirs::resolve()?.config,
+            })
+        }
+
+        /// Directory holding the registries, without creating it
+        pub fn lookup_data() -> Result<Self> {
+            Ok(Self {
+                path: MoxenDirs::resolve()?.data,
+            })
+        }
+
+        /// Directory holding cached archives, without creating it
+        pub fn lookup_cache() -> Result<Self> {
+            Ok(Self {
+                path: MoxenDirs::resolve()?.cache,
+            })
+        }
+
+        fn at(path: PathBuf) -> Result<Self> {
+            std::fs::create_dir_all(&path)
+                .with_context(|| format!("creating directory '{}'", path.display()))?;
+
+            Ok(Self { path })
+        }
+
+        pub fn dir(mut self, dir: impl AsRef<Path>) -> Result<Self> {
+            self.path = self.path.join(dir);
+            if !self.path.exists() {
+                std::fs::create_dir_all(&self.path)
+                    .with_context(|| format!("creating directory '{}'", self.path.display()))?;
+            }
+
+            Ok(self)
+        }
+
+        /// Append a directory without creating it
+        pub fn join(mut self, dir: impl AsRef<Path>) -> Self {
+            self.path = self.path.join(dir);
+            self
+        }
+
+        pub fn file(mut self, filename: impl AsRef<Path>) -> Self {
+            self.path = self.path.join(filename);
+            self
+        }
+
+        pub fn build(self) -> PathBuf {
+            self.path
+        }
+    }
+
+    /// Advisory lock on the Moxen home, held for as long as the value lives
+    pub struct HomeLock {
+        _file: File,
+    }
+
+    impl HomeLock {
+        /// Lock the Moxen home, waiting for any other moxen process to finish
+        pub fn acquire() -> Result<Self> {
+            let path = MoxenPath::new()
+                .context("loading root moxen path")?
+                .file("moxen.lock")
+                .build();
+
+            let file = File::options()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)
+                .with_context(|| format!("opening lock file {}", path.display()))?;
+
+            match file.try_lock() {
+                Ok(()) => {}
+                Err(TryLockError::WouldBlock) => {
+                    eprintln!("Waiting for another moxen process to finish...");
+                    file.lock().context("locking moxen home")?;
+                }
+                Err(TryLockError::Error(e)) => {
+                    return Err(e).context("locking moxen home");
+                }
+            }
+
+            Ok(Self { _file: file })
+        }
+    }
+
+    /// Write `contents` to a temporary file next to `path` then rename it into place
+    pub fn write_atomic(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<()> {
+        write_via_tmp(path.as_ref(), contents.as_ref(), false)
+    }
+
+    /// Like [`write_atomic`], but the file is only readable by the current user
+    pub fn write_private(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<()> {
+        write_via_tmp(path.as_ref(), contents.as_ref(), true)
+    }
+
+    fn write_via_tmp(path: &Path, contents: &[u8], private: bool) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("creating directory '{}'", dir.display()))?;
+        }
+
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+
+        let mut file = File::create(&tmp).with_context(|| format!("creating {}", tmp.display()))?;
+
+        #[cfg(unix)]
+        if private {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(std::fs::Permissions::from_mode(0o600))
+                .with_context(|| format!("restricting permissions of {}", tmp.display()))?;
+        }
+        #[cfg(not(unix))]
+        let _ = private;
+
+        file.write_all(contents)
+            .with_context(|| format!("writing {}", tmp.display()))?;
+        file.sync_all()
+            .with_context(|| format!("syncing {}", tmp.display()))?;
+        drop(file);
+
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("moving {} into place", tmp.display()))?;
+
+        Ok(())
+    }
+
+    /// Check a file against the hashes CurseForge reports for it.
+    ///
+    /// CurseForge provides SHA1 and MD5 hashes, which are told apart by length.
+    /// Unknown hash formats are ignored and a file with no known hashes passes.
+    pub fn verify_hashes(path: impl AsRef<Path>, hashes: &[String]) -> Result<bool> {
+        let path = path.as_ref();
+        let mut file = std::fs::File::open(path)
+            .with_context(|| format!("opening {} for hashing", path.display()))?;
+
+        let mut sha1 = Sha1::new();
+        let mut md5 = Md5::new();
+        let mut buf = [0u8; 64 * 1024];
+        loop {
+            let read = file
+                .read(&mut buf)
+                .with_context(|| format!("reading {} for hashing", path.display()))?;
+            if read == 0 {
+                break;
+            }
+
+            sha1.update(&buf[..read]);
+            md5.update(&buf[..read]);
+        }
+
+        let sha1 = format!("{:x}", sha1.finalize());
+        let md5 = format!("{:x}", md5.finalize());
+
+        Ok(hashes.iter().all(|hash| match hash.len() {
+            40 => hash.eq_ignore_ascii_case(&sha1),
+            32 => hash.eq_ignore_ascii_case(&md5),
+            _ => true,
+        }))
+    }
+
+    /// Extract an archive into `dst`, returning the SHA-1 of every file written
+    /// keyed by its `/` separated path relative to `dst`
+    pub fn unzip_archive(
+        src: impl AsRef<Path>,
+        dst: impl AsRef<Path>,
+    ) -> Result<BTreeMap<String, String>> {
+        unzip_archive_filtered(src, dst, |_| true)
+    }
+
+    /// Like [`unzip_archive`], only extracting the entries `keep` accepts
+    pub fn unzip_archive_filtered(
+        src: impl AsRef<Path>,
+        dst: impl AsRef<Path>,
+        keep: impl Fn(&Path) -> bool,
+    ) -> Result<BTreeMap<String, String>> {
+        let file = std::fs::File::open(&src)
+            .with_context(|| format!("opening zip archive: {}", src.as_ref().display()))?;
+
+        let mut archive = ZipArchive::new(file).context("initialising ZipArchive")?;
+        let mut written = BTreeMap::new();
+
+        for idx in 0..archive.len() {
+            let mut entry = archive.by_index(idx).context("retrieving zip entry")?;
+            let Some(entry_path) = entry.enclosed_name() else {
+                continue;
+            };
+
+            if !keep(&entry_path) {
+                continue;
+            }
+
+            let dst_path = dst.as_ref().join(&entry_path);
+
+            if entry.is_dir() {
+                std::fs::create_dir_all(&dst_path).with_context(|| {
+                    format!("creating sub-dir for zip entry - {}", dst_path.display())
+                })?;
+            } else {
+                if let Some(parent) = dst_path.parent() {
+                    std::fs::create_dir_all(parent).with_context(|| {
+                        format!(
+                            "creating parent directory {} for {}",
+                            parent.display(),
+                            dst_path.display()
+                        )
+                    })?;
+                }
+
+                let output = std::fs::File::create(&dst_path)
+                    .with_context(|| format!("creating file {}", dst_path.display()))?;
+
+                let mut output = HashingWriter {
+                    inner: output,
+                    sha1: Sha1::new(),
+                };
+                std::io::copy(&mut entry, &mut output).with_context(|| {
+                    format!(
+                        "copying {:?} to {}",
+                        entry.enclosed_name(),
+                        dst_path.display()
+                    )
+                })?;
+
+                written.insert(
+                    relative_name(&entry_path),
+                    format!("{:x}", output.sha1.finalize()),
+                );
+            }
+        }
+
+        Ok(written)
+    }
+
+    /// Zip `files` into `dst`, storing them relative to `base`
+    pub fn zip_files(base: &Path, files: &[PathBuf], dst: &Path) -> Result<()> {
+        let mut tmp = dst.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+
+        let out = File::create(&tmp).with_context(|| format!("creating {}", tmp.display()))?;
+        let mut zip = ZipWriter::new(out);
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        for file in files {
+            let relative = file.strip_prefix(base).unwrap_or(file);
+            zip.start_file(relative_name(relative), options)
+                .with_context(|| format!("adding {} to archive", file.display()))?;
+
+            let mut input =
+                File::open(file).with_context(|| format!("opening {}", file.display()))?;
+            std::io::copy(&mut input, &mut zip)
+                .with_context(|| format!("adding {} to archive", file.display()))?;
+        }
+
+        let out = zip.finish().context("finishing archive")?;
+        out.sync_all()
+            .with_context(|| format!("syncing {}", tmp.display()))?;
+        drop(out);
+
+        std::fs::rename(&tmp, dst)
+            .with_context(|| format!("moving {} into place", tmp.display()))?;
+
+        Ok(())
+    }
+
+    /// Every regular file below `dir`
+    pub fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                collect_files(&entry.path(), files)?;
+            } else if file_type.is_file() {
+                files.push(entry.path());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Unused `<timestamp>.<ext>` path in `dir`, returning the name without extension
+    pub fn timestamped_path(dir: &Path, ext: &str) -> (String, PathBuf) {
+        let stamp = jiff::Zoned::now().strftime("%Y-%m-%d_%H-%M-%S").to_string();
+        let mut name = stamp.clone();
+        let mut n = 1;
+        while dir.join(format!("{name}.{ext}")).exists() {
+            name = format!("{stamp}_{n}");
+            n += 1;
+        }
+
+        let path = dir.join(format!("{name}.{ext}"));
+        (name, path)
+    }
+
+    /// Sort key for names from [`timestamped_path`], so `_10` comes after `_2`
+    pub fn timestamp_order(name: &str) -> (&str, u32) {
+        match name.rsplit_once('_') {
+            Some((stamp, n)) if !n.contains('-') => (stamp, n.parse().unwrap_or_default()),
+            _ => (name, 0),
+        }
+    }
+
+    /// SHA-1 of a file as lowercase hex
+    pub fn sha1_file(path: impl AsRef<Path>) -> Result<String> {
+        let path = path.as_ref();
+        let mut file =
+            File::open(path).with_context(|| format!("opening {} for hashing", path.display()))?;
+
+        let mut sha1 = Sha1::new();
+        std::io::copy(&mut file, &mut sha1)
+            .with_context(|| format!("reading {} for hashing", path.display()))?;
+
+        Ok(format!("{:x}", sha1.finalize()))
+    }
+
+    /// Path relative to the AddOns dir as stored in install manifests
+    pub fn relative_name(path: &Path) -> String {
+        path.components()
+            .map(|part| part.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// Writer that hashes everything written through it
+    struct HashingWriter<W> {
+        inner: W,
+        sha1: Sha1,
+    }
+
+    impl<W: Write> Write for HashingWriter<W> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let written = self.inner.write(buf)?;
+            self.sha1.update(&buf[..written]);
+            Ok(written)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const SHA1_ABC: &str = "a9993e364706816aba3e25717850c26c9cd0d89d";
+        const MD5_ABC: &str = "900150983cd24fb0d6963f7d28e17f72";
+
+        fn write_temp(name: &str, contents: &[u8]) -> PathBuf {
+            let path = std::env::temp_dir().join(format!("moxen-{}-{name}", std::process::id()));
+            std::fs::write(&path, contents).unwrap();
+            path
+        }
+
+        #[test]
+        fn verifies_sha1_and_md5() {
+            let path = write_temp("hashes", b"abc");
+            let check = |hashes: &[&str]| {
+                let hashes: Vec<String> = hashes.iter().map(|hash| hash.to_string()).collect();
+                verify_hashes(&path, &hashes).unwrap()
+            };
+
+            assert!(check(&[SHA1_ABC, MD5_ABC]));
+            assert!(check(&[&SHA1_ABC.to_uppercase()]));
+            assert!(check(&[]));
+            assert!(check(&["not-a-known-hash-format"]));
+            assert!(!check(&[SHA1_ABC, "00000000000000000000000000000000"]));
+            assert!(!check(&["0000000000000000000000000000000000000000"]));
+
+            assert_eq!(sha1_file(&path).unwrap(), SHA1_ABC);
+            std::fs::remove_file(&path).unwrap();
+        }
+
+        #[test]
+        fn orders_timestamped_names_numerically() {
+            let mut names = vec![
+                "2024-05-01_18-30-00_10",
+                "2024-05-01_18-30-00_2",
+                "2024-05-02_08-00-00",
+                "2024-05-01_18-30-00",
+            ];
+            names.sort_by_key(|name| timestamp_order(name));
+            assert_eq!(
+                names,
+                [
+                    "2024-05-01_18-30-00",
+                    "2024-05-01_18-30-00_2",
+                    "2024-05-01_18-30-00_10",
+                    "2024-05-02_08-00-00",
+                ]
+            );
+        }
+
+        #[test]
+        fn missing_file_is_an_error() {
+            let path = std::env::temp_dir().join("moxen-does-not-exist.zip");
+            assert!(verify_hashes(&path, &[SHA1_ABC.to_string()]).is_err());
+        }
+    }
+}
+
+/// Download cache shared by every game version.
+///
+/// Archives are stored by CurseForge project and file ID
+/// (`cache/<project id>/<file id>.zip`) so flavors tracking the same file share
+/// a single copy.
+pub mod cache {
+    use anyhow::{Context, Result};
+    use std::{
+        cmp::Reverse,
+        collections::HashMap,
+        path::{Path, PathBuf},
+        time::SystemTime,
+    };
+
+    use super::{path::MoxenPath, registry::MoxenRegistry};
+    use crate::addon::Addon;
+
+    /// Project and file ID of a cached archive
+    pub type CacheKey = (i32, i32);
+
+    /// Cached archives for a single addon
+    pub struct CacheEntry {
+        pub name: String,
+        /// Not set for a folder from the old `<slug>` layout, which nothing uses any more
+        pub project_id: Option<i32>,
+        pub path: PathBuf,
+        pub archives: Vec<CachedArchive>,
+    }
+
+    impl CacheEntry {
+        pub fn size(&self) -> u64 {
+            self.archives.iter().map(|archive| archive.size).sum()
+        }
+    }
+
+    pub struct CachedArchive {
+        pub file_id: Option<i32>,
+        pub path: PathBuf,
+        pub size: u64,
+        pub modified: SystemTime,
+    }
+
+    pub fn cache_dir() -> Result<PathBuf> {
+        let cache = MoxenPath::lookup_cache()
+            .context("loading moxen cache path")?
+            .build();
+
+        Ok(cache)
+    }
+
+    /// Path to the cached archive of the addon's current main file
+    pub fn archive_path(addon: &Addon) -> Result<PathBuf> {
+        let file = MoxenPath::lookup_cache()
+            .context("loading moxen cache path")?
+            .join(addon.id.to_string())
+            .file(format!("{}.zip", addon.main_file.id))
+            .build();
+
+        Ok(file)
+    }
+
+    /// Count how many registries reference each cached archive
+    pub fn references<'a>(
+        registries: impl IntoIterator<Item = &'a MoxenRegistry>,
+    ) -> HashMap<CacheKey, usize> {
+        let mut refs = HashMap::new();
+        for addon in registries.into_iter().flat_map(|reg| reg.values()) {
+            *refs.entry((addon.id, addon.main_file.id)).or_default() += 1;
+        }
+
+        refs
+    }
+
+    /// Remove the archive if no registry references it any more.
+    ///
+    /// Returns `true` when the archive was removed.
+    pub fn release(addon: &Addon, refs: &HashMap<CacheKey, usize>) -> Result<bool> {
+        if refs
+            .get(&(addon.id, addon.main_file.id))
+            .is_some_and(|count| *count > 0)
+        {
+            return Ok(false);
+        }
+
+        let archive = archive_path(addon).context("loading archive path")?;
+        if archive.exists() {
+            std::fs::remove_file(&archive)
+                .with_context(|| format!("removing cached archive {}", archive.display()))?;
+        }
+
+        if let Some(dir) = archive.parent() {
+            remove_if_empty(dir)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Remove the archives of an entry, and its folder once nothing else is in it
+    pub fn remove_entry(entry: &CacheEntry) -> Result<()> {
+        for archive in entry.archives.iter() {
+            std::fs::remove_file(&archive.path)
+                .with_context(|| format!("removing cached archive {}", archive.path.display()))?;
+        }
+
+        remove_if_empty(&entry.path)
+    }
+
+    fn remove_if_empty(dir: &Path) -> Result<()> {
+        if std::fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_none()) {
+            std::fs::remove_dir(dir)
+                .with_context(|| format!("removing cache dir {}", dir.display()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Addon folders in the cache, archives are sorted newest first.
+    ///
+    /// Only project ID folders and the archives in them are moxen's, anything
+    /// else in the cache dir is left out.
+    pub fn entries() -> Result<Vec<CacheEntry>> {
+        let cache = cache_dir().context("loading cache dir")?;
+        let mut entries = Vec::new();
+        if !cache.exists() {
+            return Ok(entries);
+        }
+
+        for dir in std::fs::read_dir(&cache).context("reading cache dir")? {
+            let dir = dir.context("reading cache entry")?;
+            if !dir.file_type().context("cache entry type")?.is_dir() {
+                continue;
+            }
+
+            let name = dir.file_name().to_string_lossy().to_string();
+            let project_id = name.parse().ok();
+            let path = dir.path();
+            let mut archives = archives(&path)
+                .with_context(|| format!("reading cached archives in {}", path.display()))?;
+            archives.sort_by_key(|archive| Reverse(archive.modified));
+
+            entries.push(CacheEntry {
+                project_id,
+                name,
+                path,
+                archives,
+            });
+        }
+
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    fn archives(dir: &Path) -> Result<Vec<CachedArchive>> {
+        let mut archives = Vec::new();
+        for file in std::fs::read_dir(dir)? {
+            let file = file?;
+            let meta = file.metadata()?;
+            if !meta.is_file() {
+                continue;
+            }
+
+            let name = file.file_name().to_string_lossy().to_string();
+            if !name.ends_with(".zip") && !name.ends_with(".part") {
+                continue;
+            }
+
+            archives.push(CachedArchive {
+                file_id: name.split('.').next().and_then(|id| id.parse().ok()),
+                path: file.path(),
+                size: meta.len(),
+                modified: meta.modified()?,
+            });
+        }
+
+        Ok(archives)
+    }
+}
+
+pub mod registry {
+    use super::*;
+    use serde_json::Value;
+    use std::path::Path;
+
+    pub type MoxenRegistry = HashMap<i32, Addon>;
+
+    /// Upgrades applied to the raw registry JSON, entry `n` migrates schema `n` to `n + 1`
+    const MIGRATIONS: [fn(Value) -> Result<Value>; 1] = [v0_wrap_addons];
+
+    /// Current on-disk schema version of the registry
+    pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct RegistryFile<R> {
+        schema_version: u32,
+        addons: R,
+    }
+
+    /// Create any missing registries for the profile, existing ones are kept
+    pub fn initialise(profile: &str) -> Result<()> {
+        for version in VERSIONS {
+            let reg = MoxenRegistry::new();
+            let registry_path = version.registry_path(profile).context("registry path")?;
+            if registry_path.exists() {
+                continue;
+            }
+
+            write_atomic(&registry_path, serialise(&reg)?).context("writing new registry")?;
+        }
+
+        Ok(())
+    }
+
+    /// Registry written by a newer moxen, which must not be replaced by its backup
+    #[derive(Debug, thiserror::Error)]
+    #[error(
+        "registry schema v{found} is newer than this version of moxen supports (v{SCHEMA_VERSION})"
+    )]
+    pub struct NewerSchema {
+        pub found: u32,
+    }
+
+    pub fn load(profile: &str, version: &GameVersion) -> Result<MoxenRegistry> {
+        let registry_path = version.registry_path(profile).context("registry path")?;
+        let content = std::fs::read_to_string(&registry_path).context("reading registry file")?;
+        let (registry, from) = match parse(&content) {
+            Ok(parsed) => parsed,
+            Err(e) if e.is::<NewerSchema>() => {
+                return Err(e).with_context(|| format!("loading {}", registry_path.display()));
+            }
+            Err(e) => {
+                let backup = backup_path(&registry_path);
+                if !backup.exists() {
+                    return Err(e).context("deserialising registry");
+                }
+
+                eprintln!(
+                    "Registry {} is corrupt ({e:#}), falling back to {}",
+                    registry_path.display(),
+                    backup.display()
+                );
+                let content =
+                    std::fs::read_to_string(&backup).context("reading registry backup")?;
+                parse(&content).context("deserialising registry backup")?
+            }
+        };
+
+        if from != SCHEMA_VERSION {
+            save(&registry, profile, version).context("saving migrated registry")?;
+            println!(
+                "Upgraded {profile}/{version} registry from schema v{from} to v{SCHEMA_VERSION}"
+            );
+        }
+
+        Ok(registry)
+    }
+
+    /// Parse a registry as stored, without falling back to its backup or migrating it
+    pub fn validate(profile: &str, version: &GameVersion) -> Result<MoxenRegistry> {
+        let registry_path = version.registry_path(profile).context("registry path")?;
+        let content = std::fs::read_to_string(&registry_path).context("reading registry file")?;
+        let (registry, _) = parse(&content)?;
+
+        Ok(registry)
+    }
+
+    /// Whether the registry has a backup that can be loaded
+    pub fn has_valid_backup(profile: &str, version: &GameVersion) -> bool {
+        version
+            .registry_path(profile)
+            .ok()
+            .and_then(|path| std::fs::read_to_string(backup_path(&path)).ok())
+            .is_some_and(|content| parse(&content).is_ok())
+    }
+
+    /// Load the registries for every game version of every profile
+    pub fn load_all<'a>(
+        profiles: impl IntoIterator<Item = &'a String>,
+    ) -> Result<Vec<(String, GameVersion, MoxenRegistry)>> {
+        let mut registries = Vec::new();
+        for profile in profiles {
+            for version in VERSIONS {
+                let registry = load(profile, &version)
+                    .with_context(|| format!("loading {profile}/{version} registry"))?;
+                registries.push((profile.clone(), version, registry));
+            }
+        }
+
+        Ok(registries)
+    }
+
+    /// Remove every registry belonging to a profile
+    pub fn remove(profile: &str) -> Result<()> {
+        for version in VERSIONS {
+            let registry_path = version.registry_path(profile).context("registry path")?;
+            for path in [registry_path.clone(), backup_path(&registry_path)] {
+                if path.exists() {
+                    std::fs::remove_file(&path)
+                        .with_context(|| format!("removing {}", path.display()))?;
+                }
+            }
+        }
+
+        if profile != DEFAULT_PROFILE
+            && let Some(dir) = GameVersion::default()
+                .registry_path(profile)
+                .context("registry path")?
+                .parent()
+        {
+            let _ = std::fs::remove_dir(dir);
+        }
+
+        Ok(())
+    }
+
+    /// Save the registry, keeping the previous copy as a `.bak` if it was valid
+    pub fn save(registry: &MoxenRegistry, profile: &str, version: &GameVersion) -> Result<()> {
+        let registry_path = version.registry_path(profile).context("registry path")?;
+        let contents = serialise(registry)?;
+
+        if let Ok(previous) = std::fs::read_to_string(&registry_path)
+            && parse(&previous).is_ok()
+        {
+            write_atomic(backup_path(&registry_path), &previous).context("backing up registry")?;
+        }
+
+        write_atomic(&registry_path, &contents).context("saving registry to disk")?;
+
+        Ok(())
+    }
+
+    fn serialise(registry: &MoxenRegistry) -> Result<String> {
+        let file = RegistryFile {
+            schema_version: SCHEMA_VERSION,
+            addons: registry,
+        };
+
+        serde_json::to_string_pretty(&file).context("serialising registry")
+    }
+
+    /// Parse a registry of any known schema, returning the version it was stored as
+    fn parse(content: &str) -> Result<(MoxenRegistry, u32)> {
+        let mut value: Value = serde_json::from_str(content).context("parsing registry json")?;
+
+        // Registries before v1 were the bare map of addons
+        let from = value
+            .get("schemaVersion")
+            .and_then(Value::as_u64)
+            .map_or(0, |version| version as u32);
+
+        if from > SCHEMA_VERSION {
+            return Err(NewerSchema { found: from }.into());
+        }
+
+        for (idx, migration) in MIGRATIONS.iter().enumerate().skip(from as usize) {
+            value = migration(value).with_context(|| format!("migrating registry from v{idx}"))?;
+        }
+
+        let file: RegistryFile<MoxenRegistry> =
+            serde_json::from_value(value).context("deserialising registry")?;
+
+        Ok((file.addons, from))
+    }
+
+    fn v0_wrap_addons(addons: Value) -> Result<Value> {
+        Ok(serde_json::json!({
+            "schemaVersion": 1,
+            "addons": addons,
+        }))
+    }
+
+    fn backup_path(registry_path: &Path) -> PathBuf {
+        registry_path.with_extension("json.bak")
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const ADDONS: &str = r#"{
+            "42": {
+                "id": 42,
+                "status": 4,
+                "name": "Foo",
+                "slug": "foo",
+                "summary": "test addon",
+                "authors": [],
+                "mainFile": {
+                    "id": 2001,
+                    "modId": 42,
+                    "isAvailable": true,
+                    "displayName": null,
+                    "fileName": "foo.zip",
+                    "hashes": [],
+                    "fileDate": "2024-01-01T00:00:00Z",
+                    "downloadUrl": null,
+                    "gameVersions": ["11.0.2"],
+                    "modules": ["Foo"]
+                },
+                "dateModified": "2024-01-01T00:00:00Z"
+            }
+        }"#;
+
+        #[test]
+        fn parses_bare_v0_registry() {
+            let (registry, from) = parse(ADDONS).unwrap();
+            assert_eq!(from, 0);
+            assert_eq!(registry[&42].main_file.id, 2001);
+        }
+
+        #[test]
+        fn parses_current_registry() {
+            let content = format!(r#"{{"schemaVersion":{SCHEMA_VERSION},"addons":{ADDONS}}}"#);
+            let (registry, from) = parse(&content).unwrap();
+            assert_eq!(from, SCHEMA_VERSION);
+            assert_eq!(registry[&42].name, "Foo");
+        }
+
+        #[test]
+        fn serialised_registry_round_trips() {
+            let (registry, _) = parse(ADDONS).unwrap();
+            let (reparsed, from) = parse(&serialise(&registry).unwrap()).unwrap();
+            assert_eq!(from, SCHEMA_VERSION);
+            assert_eq!(reparsed.keys().collect::<Vec<_>>(), [&42]);
+        }
+
+        #[test]
+        fn newer_schema_is_not_treated_as_corruption() {
+            let content = format!(
+                r#"{{"schemaVersion":{},"addons":{{}}}}"#,
+                SCHEMA_VERSION + 1
+            );
+            let err = parse(&content).unwrap_err();
+            assert!(err.is::<NewerSchema>());
+
+            let err = parse("{ not json").unwrap_err();
+            assert!(!err.is::<NewerSchema>());
+        }
+    }
+}
+
+pub mod manifest {
+    use super::*;
+    use std::path::Path;
+
+    use super::path::sha1_file;
+
+    /// Files written when an addon was installed
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct InstallManifest {
+        pub file_id: i32,
+        /// SHA-1 of every file, keyed by its path relative to the AddOns dir
+        pub files: BTreeMap<String, String>,
+        /// Registry entry of the installed release, not recorded by older versions
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub addon: Option<Addon>,
+    }
+
+    /// A file that no longer matches its manifest
+    pub enum BrokenFile {
+        Missing(String),
+        Modified(String),
+    }
+
+    impl std::fmt::Display for BrokenFile {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::Missing(name) => write!(f, "missing  {name}"),
+                Self::Modified(name) => write!(f, "modified {name}"),
+            }
+        }
+    }
+
+    impl InstallManifest {
+        /// Compare the installed files in `addon_dir` with the manifest
+        pub fn check(&self, addon_dir: &Path) -> Vec<BrokenFile> {
+            self.files
+                .iter()
+                .filter_map(|(name, hash)| {
+                    let path = addon_dir.join(name);
+                    if !path.is_file() {
+                        return Some(BrokenFile::Missing(name.clone()));
+                    }
+
+                    match sha1_file(&path) {
+                        Ok(actual) if actual == *hash => None,
+                        _ => Some(BrokenFile::Modified(name.clone())),
+                    }
+                })
+                .collect()
+        }
+    }
+
+    fn manifest_dir(profile: &str, version: &GameVersion) -> Result<PathBuf> {
+        let dir = MoxenPath::data()
+            .context("loading moxen data path")?
+            .dir("manifests")
+            .context("loading manifests path")?
+            .dir(profile)
+            .with_context(|| format!("loading {profile} manifests path"))?
+            .dir(version.to_string())
+            .with_context(|| format!("loading {profile}/{version} manifests path"))?
+            .build();
+
+        Ok(dir)
+    }
+
+    fn manifest_path(profile: &str, version: &GameVersion, id: i32) -> Result<PathBuf> {
+        Ok(manifest_dir(profile, version)?.join(format!("{id}.json")))
+    }
+
+    pub fn save(
+        manifest: &InstallManifest,
+        profile: &str,
+        version: &GameVersion,
+        id: i32,
+    ) -> Result<()> {
+        let path = manifest_path(profile, version, id)?;
+        let contents =
+            serde_json::to_string_pretty(manifest).context("serialising install manifest")?;
+        write_atomic(&path, contents).context("writing install manifest")
+    }
+
+    /// The manifest of an installed addon, `None` if it was installed without one
+    pub fn load(profile: &str, version: &GameVersion, id: i32) -> Result<Option<InstallManifest>> {
+        let path = manifest_path(profile, version, id)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("reading {}", path.display()))?;
+        let manifest = serde_json::from_str(&content)
+            .with_context(|| format!("parsing {}", path.display()))?;
+
+        Ok(Some(manifest))
+    }
+
+    pub fn remove(profile: &str, version: &GameVersion, id: i32) -> Result<()> {
+        let path = manifest_path(profile, version, id)?;
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result.with_context(|| format!("removing {}", path.display())),
+        }
+    }
+
+    /// Remove every manifest belonging to a profile
+    pub fn remove_profile(profile: &str) -> Result<()> {
+        let dir = MoxenPath::data()
+            .context("loading moxen data path")?
+            .file("manifests")
+            .file(profile)
+            .build();
+
+        match std::fs::remove_dir_all(&dir) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result.with_context(|| format!("removing {}", dir.display())),
+        }
+    }
+}
+
+pub mod backup {
+    use super::*;
+    use std::{collections::HashSet, path::Path};
+
+    use super::path::{collect_files, timestamp_order, timestamped_path, zip_files};
+
+    /// A zipped copy of a game version's WTF folder
+    pub struct Backup {
+        pub name: String,
+        pub path: PathBuf,
+        pub size: u64,
+    }
+
+    fn backup_dir(profile: &str, version: &GameVersion) -> Result<PathBuf> {
+        let dir = MoxenPath::data()
+            .context("loading moxen data path")?
+            .dir("backups")
+            .context("loading backups path")?
+            .dir(profile)
+            .with_context(|| format!("loading {profile} backups path"))?
+            .dir(version.to_string())
+            .with_context(|| format!("loading {profile}/{version} backups path"))?
+            .build();
+
+        Ok(dir)
+    }
+
+    /// Zip the `WTF` folder inside `flavor_dir` into a timestamped backup.
+    ///
+    /// When `modules` is given only the SavedVariables of those modules are kept.
+    pub fn create(
+        flavor_dir: &Path,
+        profile: &str,
+        version: &GameVersion,
+        modules: Option<&HashSet<String>>,
+    ) -> Result<Backup> {
+        let wtf = flavor_dir.join("WTF");
+        if !wtf.is_dir() {
+            anyhow::bail!(
+                "no WTF folder in {}, has the game been started yet?",
+                flavor_dir.display()
+            );
+        }
+
+        let mut files = Vec::new();
+        collect_files(&wtf, &mut files).with_context(|| format!("reading {}", wtf.display()))?;
+
+        if let Some(modules) = modules {
+            files.retain(|file| is_saved_variables_of(file, modules));
+        }
+
+        if files.is_empty() {
+            anyhow::bail!("nothing to back up in {}", wtf.display());
+        }
+
+        let (name, path) = timestamped_path(&backup_dir(profile, version)?, "zip");
+        zip_files(flavor_dir, &files, &path).context("writing backup archive")?;
+
+        let size = std::fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0);
+        Ok(Backup { name, path, size })
+    }
+
+    /// Backups of a game version, oldest first
+    pub fn list(profile: &str, version: &GameVersion) -> Result<Vec<Backup>> {
+        let dir = backup_dir(profile, version)?;
+        let mut backups = Vec::new();
+        for entry in std::fs::read_dir(&dir).context("reading backups dir")? {
+            let entry = entry.context("reading backup entry")?;
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "zip") {
+                continue;
+            }
+
+            let Some(name) = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+            else {
+                continue;
+            };
+
+            let size = entry.metadata().map(|meta| meta.len()).unwrap_or(0);
+            backups.push(Backup { name, path, size });
+        }
+
+        // Names are timestamps, so they sort chronologically
+        backups.sort_by(|a, b| timestamp_order(&a.name).cmp(&timestamp_order(&b.name)));
+        Ok(backups)
+    }
+
+    /// Find a backup by name, with or without the `.zip` extension
+    pub fn find(profile: &str, version: &GameVersion, name: &str) -> Result<Option<Backup>> {
+        let name = name.strip_suffix(".zip").unwrap_or(name);
+        Ok(list(profile, version)?
+            .into_iter()
+            .find(|backup| backup.name == name))
+    }
+
+    /// `SavedVariables/<Module>.lua` (and its `.bak`), account wide or per character
+    fn is_saved_variables_of(file: &Path, modules: &HashSet<String>) -> bool {
+        let in_saved_variables = file
+            .parent()
+            .and_then(Path::file_name)
+            .is_some_and(|dir| dir == "SavedVariables");
+
+        // Module names may contain dots themselves, e.g. `LibSharedMedia-3.0`
+        let name = file.file_name().map(|name| name.to_string_lossy());
+        let module = name.as_deref().and_then(|name| {
+            name.strip_suffix(".lua.bak")
+                .or_else(|| name.strip_suffix(".lua"))
+        });
+
+        in_saved_variables && module.is_some_and(|module| modules.contains(module))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn matches_saved_variables_by_module() {
+            let modules = HashSet::from(["Foo".to_string(), "LibSharedMedia-3.0".to_string()]);
+            let matches = |path: &str| is_saved_variables_of(Path::new(path), &modules);
+
+            assert!(matches("WTF/Account/ME/SavedVariables/Foo.lua"));
+            assert!(matches("WTF/Account/ME/SavedVariables/Foo.lua.bak"));
+            assert!(matches(
+                "WTF/Account/ME/Realm/Char/SavedVariables/LibSharedMedia-3.0.lua"
+            ));
+            assert!(!matches(
+                "WTF/Account/ME/SavedVariables/LibSharedMedia-3.lua"
+            ));
+            assert!(!matches("WTF/Account/ME/SavedVariables/Bar.lua"));
+            assert!(!matches("WTF/Account/ME/SavedVariables/Foo.txt"));
+            assert!(!matches("WTF/Account/ME/Foo.lua"));
+        }
+    }
+}
+
+pub mod snapshot {
+    use super::*;
+    use std::path::Path;
+
+    use super::path::{collect_files, timestamp_order, timestamped_path, zip_files};
+
+    /// Snapshots kept per addon, older ones are removed
+    const SNAPSHOTS_TO_KEEP: usize = 3;
+
+    /// What a snapshot contains, stored next to its archive
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct SnapshotInfo {
+        /// Registry entry of the release in the snapshot
+        pub addon: Addon,
+        /// File that was installed, if known
+        pub file_id: Option<i32>,
+        /// Module folders in the archive
+        pub modules: Vec<String>,
+    }
+
+    /// Copy of an addon's module folders taken before they were changed
+    pub struct Snapshot {
+        pub name: String,
+        pub path: PathBuf,
+        pub info: SnapshotInfo,
+    }
+
+    fn snapshot_dir(profile: &str, version: &GameVersion, id: i32) -> Result<PathBuf> {
+        let dir = MoxenPath::data()
+            .context("loading moxen data path")?
+            .dir("snapshots")
+            .context("loading snapshots path")?
+            .dir(profile)
+            .with_context(|| format!("loading {profile} snapshots path"))?
+            .dir(version.to_string())
+            .with_context(|| format!("loading {profile}/{version} snapshots path"))?
+            .dir(id.to_string())
+            .with_context(|| format!("loading {id} snapshots path"))?
+            .build();
+
+        Ok(dir)
+    }
+
+    /// Archive the `modules` of an addon that exist in `addon_dir`, returning
+    /// `None` when none are installed
+    pub fn take(
+        addon: &Addon,
+        modules: &[String],
+        file_id: Option<i32>,
+        addon_dir: &Path,
+        profile: &str,
+        version: &GameVersion,
+    ) -> Result<Option<Snapshot>> {
+        let modules: Vec<String> = modules
+            .iter()
+            .filter(|module| addon_dir.join(module).is_dir())
+            .cloned()
+            .collect();
+
+        let mut files = Vec::new();
+        for module in modules.iter() {
+            let dir = addon_dir.join(module);
+            collect_files(&dir, &mut files)
+                .with_context(|| format!("reading {}", dir.display()))?;
+        }
+
+        if files.is_empty() {
+            return Ok(None);
+        }
+
+        let dir = snapshot_dir(profile, version, addon.id)?;
+        let (name, path) = timestamped_path(&dir, "zip");
+        zip_files(addon_dir, &files, &path).context("writing snapshot archive")?;
+
+        let info = SnapshotInfo {
+            addon: addon.clone(),
+            file_id,
+            modules,
+        };
+        let contents = serde_json::to_string_pretty(&info).context("serialising snapshot")?;
+        write_atomic(path.with_extension("json"), contents).context("writing snapshot info")?;
+
+        rotate(profile, version, addon.id).context("removing old snapshots")?;
+
+        Ok(Some(Snapshot { name, path, info }))
+    }
+
+    /// The most recent snapshot of an addon
+    pub fn latest(profile: &str, version: &GameVersion, id: i32) -> Result<Option<Snapshot>> {
+        let Some((name, path)) = archives(profile, version, id)?.pop() else {
+            return Ok(None);
+        };
+
+        let info_path = path.with_extension("json");
+        let content = std::fs::read_to_string(&info_path)
+            .with_context(|| format!("reading {}", info_path.display()))?;
+        let info = serde_json::from_str(&content)
+            .with_context(|| format!("parsing {}", info_path.display()))?;
+
+        Ok(Some(Snapshot { name, path, info }))
+    }
+
+    /// Snapshot archives of an addon, oldest first
+    fn archives(profile: &str, version: &GameVersion, id: i32) -> Result<Vec<(String, PathBuf)>> {
+        let dir = snapshot_dir(profile, version, id)?;
+        let mut archives = Vec::new();
+        for entry in std::fs::read_dir(&dir).context("reading snapshots dir")? {
+            let path = entry.context("reading snapshot entry")?.path();
+            if path.extension().is_none_or(|ext| ext != "zip") {
+                continue;
+            }
+
+            if let Some(name) = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+            {
+                archives.push((name, path));
+            }
+        }
+
+        // Names are timestamps, so they sort chronologically
+        archives.sort_by(|(a, _), (b, _)| timestamp_order(a).cmp(&timestamp_order(b)));
+        Ok(archives)
+    }
+
+    fn rotate(profile: &str, version: &GameVersion, id: i32) -> Result<()> {
+        let archives = archives(profile, version, id)?;
+        let excess = archives.len().saturating_sub(SNAPSHOTS_TO_KEEP);
+        for (_, path) in archives.into_iter().take(excess) {
+            for file in [path.with_extension("json"), path] {
+                if file.exists() {
+                    std::fs::remove_file(&file)
+                        .with_context(|| format!("removing {}", file.display()))?;
+                }
+            }
+        }
 
         Ok(())
     }
 }
+
+pub mod toc {
+    use std::path::{Path, PathBuf};
+
+    use super::GameVersion;
+
+    /// Metadata from a module's `.toc` file
+    #[derive(Debug, Default)]
+    pub struct Toc {
+        pub title: Option<String>,
+        pub version: Option<String>,
+        /// Client builds the module supports, e.g. `110002` for 11.0.2
+        pub interfaces: Vec<u32>,
+        /// Modules that have to be installed for this one to load
+        pub required_deps: Vec<String>,
+    }
+
+    impl Toc {
+        pub fn parse(content: &str) -> Self {
+            let mut toc = Self::default();
+            for line in content.lines() {
+                let Some(tag) = line.trim_start_matches('\u{feff}').strip_prefix("##") else {
+                    continue;
+                };
+                let Some((key, value)) = tag.split_once(':') else {
+                    continue;
+                };
+
+                let value = value.trim();
+                let key = key.trim().to_ascii_lowercase();
+                match key.as_str() {
+                    "interface" => {
+                        toc.interfaces = value
+                            .split(',')
+                            .filter_map(|build| build.trim().parse().ok())
+                            .collect();
+                    }
+                    "title" => toc.title = Some(strip_colours(value)),
+                    "version" => toc.version = Some(value.to_string()),
+                    // The client treats any tag starting with `Dep` as required
+                    key if key == "requireddeps" || key.starts_with("dep") => {
+                        toc.required_deps.extend(
+                            value
+                                .split(',')
+                                .map(str::trim)
+                                .filter(|dep| !dep.is_empty())
+                                .map(str::to_string),
+                        );
+                    }
+                    _ => {}
+                }
+            }
+
+            toc
+        }
+
+        /// Read the TOC of an installed module, preferring the one for the game version
+        pub fn load(module_dir: &Path, version: &GameVersion) -> Option<Self> {
+            let content = std::fs::read_to_string(find(module_dir, version)?).ok()?;
+            Some(Self::parse(&content))
+        }
+
+        /// Built for an older client than `interface`
+        pub fn is_outdated(&self, interface: u32) -> bool {
+            !self.interfaces.is_empty() && self.interfaces.iter().all(|build| *build < interface)
+        }
+    }
+
+    /// Interface number of the installed client from the `.build.info` in the
+    /// WoW root, e.g. `110002` for 11.0.2
+    pub fn client_interface(root: &Path, version: &GameVersion) -> Option<u32> {
+        let content = std::fs::read_to_string(root.join(".build.info")).ok()?;
+        build_info_interface(&content, version)
+    }
+
+    /// Interface of the active build of the game version in `.build.info` content
+    fn build_info_interface(content: &str, version: &GameVersion) -> Option<u32> {
+        let mut lines = content.lines();
+        let header: Vec<&str> = lines
+            .next()?
+            .split('|')
+            .map(|column| column.split('!').next().unwrap_or_default())
+            .collect();
+
+        let column = |name: &str| header.iter().position(|column| *column == name);
+        let (product, build, active) = (column("Product")?, column("Version")?, column("Active"));
+
+        lines
+            .map(|line| line.split('|').collect::<Vec<_>>())
+            .filter(|row| active.is_none_or(|idx| row.get(idx) == Some(&"1")))
+            .find(|row| {
+                row.get(product)
+                    .is_some_and(|product| products(version).contains(product))
+            })
+            .and_then(|row| interface(row.get(build)?))
+    }
+
+    /// Battle.net product codes of a game version
+    fn products(version: &GameVersion) -> &'static [&'static str] {
+        match version {
+            GameVersion::Retail => &["wow"],
+            GameVersion::Beta => &["wow_beta"],
+            GameVersion::Ptr => &["wowt", "wowxptr"],
+            GameVersion::Classic => &["wow_classic"],
+            GameVersion::ClassicEra => &["wow_classic_era"],
+        }
+    }
+
+    /// `11.0.2.56421` to `110002`
+    fn interface(build: &str) -> Option<u32> {
+        let mut parts = build.split('.').map(|part| part.parse::<u32>().ok());
+        let (major, minor, patch) = (parts.next()??, parts.next()??, parts.next()??);
+        Some(major * 10000 + minor * 100 + patch)
+    }
+
+    /// Game version specific TOC suffixes, most specific first
+    fn suffixes(version: &GameVersion) -> &'static [&'static str] {
+        match version {
+            GameVersion::Retail | GameVersion::Beta | GameVersion::Ptr => &["mainline"],
+            GameVersion::Classic => &["mists", "cata", "wrath", "classic"],
+            GameVersion::ClassicEra => &["vanilla", "classic"],
+        }
+    }
+
+    fn find(module_dir: &Path, version: &GameVersion) -> Option<PathBuf> {
+        let module = module_dir.file_name()?.to_string_lossy().to_lowercase();
+        let tocs: Vec<(String, PathBuf)> = std::fs::read_dir(module_dir)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                (
+                    entry.file_name().to_string_lossy().to_lowercase(),
+                    entry.path(),
+                )
+            })
+            .filter(|(name, _)| name.ends_with(".toc"))
+            .collect();
+
+        let candidates = suffixes(version)
+            .iter()
+            .flat_map(|suffix| {
+                [
+                    format!("{module}_{suffix}.toc"),
+                    format!("{module}-{suffix}.toc"),
+                ]
+            })
+            .chain(std::iter::once(format!("{module}.toc")));
+
+        for candidate in candidates {
+            if let Some((_, path)) = tocs.iter().find(|(name, _)| *name == candidate) {
+                return Some(path.clone());
+            }
+        }
+
+        None
+    }
+
+    /// Remove `|cAARRGGBB` and `|r` colour escapes
+    fn strip_colours(title: &str) -> String {
+        let mut plain = String::with_capacity(title.len());
+        let mut chars = title.chars();
+        while let Some(c) = chars.next() {
+            if c != '|' {
+                plain.push(c);
+                continue;
+            }
+
+            match chars.next() {
+                Some('c' | 'C') => {
+                    chars.nth(7);
+                }
+                Some('r' | 'R') => {}
+                Some(other) => {
+                    plain.push(c);
+                    plain.push(other);
+                }
+                None => plain.push(c),
+            }
+        }
+
+        plain.trim().to_string()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn parses_toc_tags() {
+            let toc = Toc::parse(
+                "\u{feff}## Interface: 110002, 40400\n\
+                 ## Title: |cff00ff00Foo|r Bar\n\
+                 ## version: 1.2.3\n\
+                 ## RequiredDeps: LibStub, Ace3\n\
+                 ## Dependencies: Bar\n\
+                 ## OptionalDeps: Baz\n\
+                 # ## Interface: 1\n\
+                 Foo.lua\n",
+            );
+
+            assert_eq!(toc.interfaces, [110002, 40400]);
+            assert_eq!(toc.title.as_deref(), Some("Foo Bar"));
+            assert_eq!(toc.version.as_deref(), Some("1.2.3"));
+            assert_eq!(toc.required_deps, ["LibStub", "Ace3", "Bar"]);
+        }
+
+        #[test]
+        fn outdated_only_when_every_interface_is_older() {
+            let toc = Toc::parse("## Interface: 110002, 40400");
+            assert!(toc.is_outdated(110005));
+            assert!(!toc.is_outdated(110002));
+            assert!(!Toc::default().is_outdated(110005));
+        }
+
+        #[test]
+        fn converts_builds_to_interfaces() {
+            assert_eq!(interface("11.0.2.56421"), Some(110002));
+            assert_eq!(interface("1.15.4"), Some(11504));
+            assert_eq!(interface("11.0"), None);
+            assert_eq!(interface("11.x.2"), None);
+        }
+
+        #[test]
+        fn strips_colour_escapes() {
+            assert_eq!(strip_colours("|cFFFF0000Red|r Plain"), "Red Plain");
+            assert_eq!(strip_colours("Pipe || stays"), "Pipe || stays");
+            assert_eq!(strip_colours(" Trailing |"), "Trailing |");
+        }
+
+        #[test]
+        fn reads_active_build_for_version() {
+            let content = "Branch!STRING:0|Active!DEC:1|Version!STRING:0|Product!STRING:0\n\
+                           eu|1|11.0.5.57212|wow\n\
+                           eu|0|1.15.3.55646|wow_classic_era\n\
+                           eu|1|1.15.4.56738|wow_classic_era\n";
+
+            assert_eq!(
+                build_info_interface(content, &GameVersion::Retail),
+                Some(110005)
+            );
+            assert_eq!(
+                build_info_interface(content, &GameVersion::ClassicEra),
+                Some(11504)
+            );
+            assert_eq!(build_info_interface(content, &GameVersion::Ptr), None);
+        }
+    }
+}
+
+pub mod secret {
+    use anyhow::{Context, Result};
+    use serde::{Deserialize, Serialize};
+    use std::{process::Command, str::FromStr};
+
+    use super::path::{MoxenPath, write_private};
+
+    /// Keyring service the API keys are stored under
+    const SERVICE: &str = "moxen";
+
+    /// Where an API key is read from
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(try_from = "String", into = "String")]
+    pub enum ApiKeySource {
+        /// The OS secret service (Secret Service, Keychain or Credential Manager)
+        Keyring,
+        /// A file in the Moxen home directory only readable by the current user
+        File,
+        /// An environment variable
+        Env(String),
+        /// The output of a shell command, e.g. `pass show curseforge`
+        Command(String),
+    }
+
+    impl ApiKeySource {
+        /// The keyring when a secret service is reachable, a private file otherwise
+        pub fn preferred() -> Self {
+            let available = keyring::Entry::new(SERVICE, &account(None))
+                .and_then(|entry| entry.get_password())
+                .map_or_else(|e| matches!(e, keyring::Error::NoEntry), |_| true);
+
+            if available { Self::Keyring } else { Self::File }
+        }
+
+        /// Keys can only be stored in the keyring or a file, the others are read only
+        pub fn is_writable(&self) -> bool {
+            matches!(self, Self::Keyring | Self::File)
+        }
+
+        pub fn read(&self, account: &str) -> Result<String> {
+            let key = match self {
+                Self::Keyring => match entry(account)?.get_password() {
+                    Err(keyring::Error::NoEntry) => anyhow::bail!(
+                        "no API key for '{account}' in the keyring, set one with `moxen config set api_key`"
+                    ),
+                    result => result.context("reading API key from the keyring")?,
+                },
+                Self::File => {
+                    let path = key_path(account)?;
+                    warn_if_shared(&path);
+                    std::fs::read_to_string(&path)
+                        .with_context(|| format!("reading API key from {}", path.display()))?
+                }
+                Self::Env(var) => {
+                    std::env::var(var).with_context(|| format!("reading API key from ${var}"))?
+                }
+                Self::Command(command) => run(command)?,
+            };
+
+            let key = key.trim();
+            if key.is_empty() {
+                anyhow::bail!("the API key from {self} is empty");
+            }
+
+            Ok(key.to_string())
+        }
+
+        pub fn write(&self, account: &str, key: &str) -> Result<()> {
+            match self {
+                Self::Keyring => entry(account)?
+                    .set_password(key)
+                    .context("storing API key in the keyring"),
+                Self::File => {
+                    write_private(key_path(account)?, key).context("storing API key in a file")
+                }
+                Self::Env(_) | Self::Command(_) => {
+                    anyhow::bail!("the API key is read from {self} and can't be changed by moxen")
+                }
+            }
+        }
+
+        /// Remove a stored key, missing keys are ignored
+        pub fn delete(&self, account: &str) -> Result<()> {
+            match self {
+                Self::Keyring => match entry(account)?.delete_credential() {
+                    Err(keyring::Error::NoEntry) => Ok(()),
+                    result => result.context("removing API key from the keyring"),
+                },
+                Self::File => match std::fs::remove_file(key_path(account)?) {
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                    result => result.context("removing API key file"),
+                },
+                Self::Env(_) | Self::Command(_) => Ok(()),
+            }
+        }
+    }
+
+    impl std::fmt::Display for ApiKeySource {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::Keyring => write!(f, "keyring"),
+                Self::File => write!(f, "file"),
+                Self::Env(var) => write!(f, "env:{var}"),
+                Self::Command(command) => write!(f, "command:{command}"),
+            }
+        }
+    }
+
+    impl FromStr for ApiKeySource {
+        type Err = anyhow::Error;
+
+        fn from_str(s: &str) -> Result<Self> {
+            let source = match s.trim().split_once(':') {
+                None if s.trim() == "keyring" => Self::Keyring,
+                None if s.trim() == "file" => Self::File,
+                Some(("env", var)) if !var.trim().is_empty() => Self::Env(var.trim().to_string()),
+                Some(("command", command)) if !command.trim().is_empty() => {
+                    Self::Command(command.trim().to_string())
+                }
+                _ => anyhow::bail!(
+                    "invalid API key source '{s}', expected keyring, file, env:<VAR> or command:<COMMAND>"
+                ),
+            };
+
+            Ok(source)
+        }
+    }
+
+    impl TryFrom<String> for ApiKeySource {
+        type Error = anyhow::Error;
+
+        fn try_from(value: String) -> Result<Self> {
+            value.parse()
+        }
+    }
+
+    impl From<ApiKeySource> for String {
+        fn from(source: ApiKeySource) -> Self {
+            source.to_string()
+        }
+    }
+
+    /// Name a key is stored under, the global key or a profile's own key
+    pub fn account(profile: Option<&str>) -> String {
+        match profile {
+            Some(name) => format!("api_key.{name}"),
+            None => "api_key".to_string(),
+        }
+    }
+
+    fn entry(account: &str) -> Result<keyring::Entry> {
+        keyring::Entry::new(SERVICE, account).context("opening keyring entry")
+    }
+
+    fn key_path(account: &str) -> Result<std::path::PathBuf> {
+        Ok(MoxenPath::lookup_config()
+            .context("loading root moxen path")?
+            .file(account)
+            .build())
+    }
+
+    #[cfg(unix)]
+    fn warn_if_shared(path: &std::path::Path) {
+        use std::os::unix::fs::PermissionsExt;
+
+        if let Ok(meta) = std::fs::metadata(path)
+            && meta.permissions().mode() & 0o077 != 0
+        {
+            eprintln!(
+                "Warning: {} is readable by other users, run `chmod 600` on it",
+                path.display()
+            );
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn warn_if_shared(_: &std::path::Path) {}
+
+    fn run(command: &str) -> Result<String> {
+        let mut shell = if cfg!(windows) {
+            let mut shell = Command::new("cmd");
+            shell.arg("/C");
+            shell
+        } else {
+            let mut shell = Command::new("sh");
+            shell.arg("-c");
+            shell
+        };
+
+        let output = shell
+            .arg(command)
+            .output()
+            .with_context(|| format!("running `{command}`"))?;
+
+        if !output.status.success() {
+            anyhow::bail!("`{command}` failed with {}", output.status);
+        }
+
+        String::from_utf8(output.stdout).context("API key command printed invalid UTF-8")
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn parses_sources() {
+            assert_eq!(
+                "keyring".parse::<ApiKeySource>().unwrap(),
+                ApiKeySource::Keyring
+            );
+            assert_eq!(
+                " file ".parse::<ApiKeySource>().unwrap(),
+                ApiKeySource::File
+            );
+            assert_eq!(
+                "env: CURSEFORGE_KEY".parse::<ApiKeySource>().unwrap(),
+                ApiKeySource::Env("CURSEFORGE_KEY".to_string())
+            );
+            assert_eq!(
+                "command:pass show curseforge"
+                    .parse::<ApiKeySource>()
+                    .unwrap(),
+                ApiKeySource::Command("pass show curseforge".to_string())
+            );
+        }
+
+        #[test]
+        fn rejects_invalid_sources() {
+            for source in ["", "vault", "env:", "command: ", "keyring:x", "file:key"] {
+                assert!(source.parse::<ApiKeySource>().is_err(), "{source}");
+            }
+        }
+
+        #[test]
+        fn display_round_trips() {
+            for source in [
+                ApiKeySource::Keyring,
+                ApiKeySource::File,
+                ApiKeySource::Env("KEY".to_string()),
+                ApiKeySource::Command("cat key:file".to_string()),
+            ] {
+                assert_eq!(source.to_string().parse::<ApiKeySource>().unwrap(), source);
+            }
+        }
+
+        #[test]
+        fn only_stored_sources_are_writable() {
+            assert!(ApiKeySource::Keyring.is_writable());
+            assert!(ApiKeySource::File.is_writable());
+            assert!(!ApiKeySource::Env("KEY".to_string()).is_writable());
+            assert!(!ApiKeySource::Command("true".to_string()).is_writable());
+        }
+
+        #[test]
+        fn accounts_are_per_profile() {
+            assert_eq!(account(None), "api_key");
+            assert_eq!(account(Some("ptr")), "api_key.ptr");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_v0_config_into_default_profile() {
+        let content = r#"
+            api_key = "secret"
+            install_dir = "/games/wow"
+            version = "classic_era"
+        "#;
+
+        let (mut table, from) = migrate_config(content).unwrap();
+        assert_eq!(from, 0);
+
+        let keys = take_plaintext_keys(&mut table);
+        assert_eq!(keys, [(None, "secret".to_string())]);
+
+        let cfg: MoxenConfig = table.try_into().unwrap();
+        assert_eq!(cfg.active_profile, DEFAULT_PROFILE);
+        let profile = &cfg.profiles[DEFAULT_PROFILE];
+        assert_eq!(profile.install_dir.0, PathBuf::from("/games/wow"));
+        assert_eq!(profile.version, GameVersion::ClassicEra);
+        assert_eq!(cfg.max_parallel_downloads, DEFAULT_MAX_PARALLEL_DOWNLOADS);
+        cfg.validate().unwrap();
+    }
+
+    #[test]
+    fn current_config_is_left_alone() {
+        let content = format!(
+            r#"
+            schema_version = {CONFIG_SCHEMA_VERSION}
+            active_profile = "ptr"
+
+            [profiles.ptr]
+            install_dir = "/games/wow"
+            version = "ptr"
+            api_key = "profile-secret"
+        "#
+        );
+
+        let (mut table, from) = migrate_config(&content).unwrap();
+        assert_eq!(from, CONFIG_SCHEMA_VERSION);
+        assert_eq!(
+            take_plaintext_keys(&mut table),
+            [(Some("ptr".to_string()), "profile-secret".to_string())]
+        );
+
+        let cfg: MoxenConfig = table.try_into().unwrap();
+        assert_eq!(cfg.profiles["ptr"].version, GameVersion::Ptr);
+    }
+
+    #[test]
+    fn rejects_newer_config_schema() {
+        let content = format!("schema_version = {}", CONFIG_SCHEMA_VERSION + 1);
+        assert!(migrate_config(&content).is_err());
+    }
+
+    #[test]
+    fn rejects_zero_timeouts() {
+        let content = r#"
+            install_dir = "/games/wow"
+            version = "retail"
+            connect_timeout_secs = 0
+        "#;
+
+        let (table, _) = migrate_config(content).unwrap();
+        let cfg: MoxenConfig = table.try_into().unwrap();
+        assert!(cfg.validate().is_err());
+    }
+}